@@ -0,0 +1,50 @@
+#![feature(test)]
+
+extern crate test;
+extern crate utf8;
+
+use std::io::{self, BufRead, Read};
+use utf8::BufReadDecoder;
+
+/// A pathological reader whose buffer holds one byte at a time.
+struct OneByteAtATime<'a>(&'a [u8]);
+
+impl<'a> Read for OneByteAtATime<'a> {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        unimplemented!()
+    }
+}
+
+impl<'a> BufRead for OneByteAtATime<'a> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(&self.0[..self.0.len().min(1)])
+    }
+
+    fn consume(&mut self, bytes: usize) {
+        self.0 = &self.0[bytes..]
+    }
+}
+
+fn input() -> Vec<u8> {
+    "ศไทย中华Việt Nam, mostly ASCII text. ".repeat(100).into_bytes()
+}
+
+fn decode_all<B: BufRead>(mut decoder: BufReadDecoder<B>) {
+    while let Some(result) = decoder.next_lossy() {
+        test::black_box(result.unwrap());
+    }
+}
+
+#[bench]
+fn bench_one_byte_at_a_time(bencher: &mut test::Bencher) {
+    let input = input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| decode_all(BufReadDecoder::new(OneByteAtATime(&input))))
+}
+
+#[bench]
+fn bench_one_byte_at_a_time_with_min_fill(bencher: &mut test::Bencher) {
+    let input = input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| decode_all(BufReadDecoder::with_min_fill(OneByteAtATime(&input), 256)))
+}
@@ -0,0 +1,22 @@
+#![feature(test)]
+
+extern crate test;
+extern crate utf8;
+
+fn input() -> String {
+    "Hello, ศไทย中华Việt Nam \u{1F4A9}! ".repeat(1000)
+}
+
+#[bench]
+fn bench_count_chars(bencher: &mut test::Bencher) {
+    let input = input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| utf8::count_chars(test::black_box(&input)))
+}
+
+#[bench]
+fn bench_std_chars_count(bencher: &mut test::Bencher) {
+    let input = input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| test::black_box(&input).chars().count())
+}
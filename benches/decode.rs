@@ -0,0 +1,89 @@
+#![feature(test)]
+
+extern crate test;
+extern crate utf8;
+
+#[path = "../tests/shared/data.rs"]
+mod data;
+
+use utf8::{decode, DecodeError};
+
+/// Run `decode` to the end of the input, without building any output.
+fn decode_all(mut input: &[u8]) {
+    loop {
+        match decode(input) {
+            Ok(s) => {
+                test::black_box(s);
+                return
+            }
+            Err(DecodeError::Incomplete { valid_prefix, .. }) => {
+                test::black_box(valid_prefix);
+                return
+            }
+            Err(DecodeError::Invalid { valid_prefix, remaining_input, .. }) => {
+                test::black_box(valid_prefix);
+                input = remaining_input;
+            }
+        }
+    }
+}
+
+#[bench]
+fn bench_decode_mixed(bencher: &mut test::Bencher) {
+    bencher.bytes = data::DECODED_LOSSY.iter().map(|&(input, _expected)| input.len() as u64).sum();
+    bencher.iter(|| {
+        for &(input, _expected) in data::DECODED_LOSSY {
+            decode_all(input)
+        }
+    })
+}
+
+/// Run `decode_positions` to the end of the input, like `decode_all`.
+fn decode_positions_all(mut input: &[u8]) {
+    while let Err((valid_up_to, Some(error_len))) = utf8::decode_positions(input) {
+        input = &input[valid_up_to + error_len..];
+    }
+    test::black_box(input);
+}
+
+fn mostly_valid_input() -> Vec<u8> {
+    let mut input = Vec::new();
+    for &(bytes, _expected) in data::DECODED_LOSSY {
+        input.extend_from_slice(bytes);
+        input.extend_from_slice(&[b'a'; 200]);
+        input.extend_from_slice("ศไทย中华Việt Nam".as_bytes());
+    }
+    input
+}
+
+#[bench]
+fn bench_decode_mostly_valid(bencher: &mut test::Bencher) {
+    let input = mostly_valid_input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| decode_all(&input))
+}
+
+#[bench]
+fn bench_decode_positions_mostly_valid(bencher: &mut test::Bencher) {
+    let input = mostly_valid_input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| decode_positions_all(&input))
+}
+
+fn ascii_input() -> Vec<u8> {
+    "The quick brown fox jumps over the lazy dog. ".repeat(1000).into_bytes()
+}
+
+#[bench]
+fn bench_is_valid_utf8_ascii(bencher: &mut test::Bencher) {
+    let input = ascii_input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| utf8::is_valid_utf8(test::black_box(&input)))
+}
+
+#[bench]
+fn bench_std_from_utf8_is_ok_ascii(bencher: &mut test::Bencher) {
+    let input = ascii_input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| std::str::from_utf8(test::black_box(&input)).is_ok())
+}
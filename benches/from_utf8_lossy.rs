@@ -28,3 +28,29 @@ fn bench_std_string_from_utf8_lossy(bencher: &mut test::Bencher) {
         }
     })
 }
+
+fn valid_input() -> Vec<u8> {
+    "ศไทย中华Việt Nam, mostly ASCII text. ".repeat(1000).into_bytes()
+}
+
+/// The baseline for `bench_into_valid_utf8`, which also needs a new `Vec` per iteration.
+#[bench]
+fn bench_clone_vec(bencher: &mut test::Bencher) {
+    let input = valid_input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| test::black_box(input.clone()))
+}
+
+#[bench]
+fn bench_into_valid_utf8(bencher: &mut test::Bencher) {
+    let input = valid_input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| test::black_box(utf8::into_valid_utf8(input.clone())))
+}
+
+#[bench]
+fn bench_std_from_utf8_lossy_into_owned(bencher: &mut test::Bencher) {
+    let input = valid_input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| test::black_box(String::from_utf8_lossy(&input.clone()).into_owned()))
+}
@@ -0,0 +1,32 @@
+#![feature(test)]
+
+extern crate test;
+extern crate utf8;
+
+use utf8::LossyDecoder;
+
+fn input() -> Vec<u8> {
+    "ศไทย中华Việt Nam, mostly ASCII text. ".repeat(100).into_bytes()
+}
+
+#[bench]
+fn bench_feed_one_byte_at_a_time(bencher: &mut test::Bencher) {
+    let input = input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| {
+        let mut decoder = LossyDecoder::new(|s| { test::black_box(s); });
+        for byte in input.chunks(1) {
+            decoder.feed(byte);
+        }
+    })
+}
+
+#[bench]
+fn bench_feed_whole_input(bencher: &mut test::Bencher) {
+    let input = input();
+    bencher.bytes = input.len() as u64;
+    bencher.iter(|| {
+        let mut decoder = LossyDecoder::new(|s| { test::black_box(s); });
+        decoder.feed(&input);
+    })
+}
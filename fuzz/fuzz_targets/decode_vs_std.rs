@@ -0,0 +1,36 @@
+//! Compare `decode` and `BufReadDecoder` against `std::str::from_utf8`.
+//!
+//! Run with `cargo +nightly fuzz run decode_vs_std` from the repository root.
+
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate utf8;
+
+use std::io::BufReader;
+use std::str;
+
+fuzz_target!(|data: &[u8]| {
+    let (capacity, input) = match data.split_first() {
+        Some((&first, rest)) => (first as usize + 1, rest),
+        None => return,
+    };
+
+    match (utf8::decode(input), str::from_utf8(input)) {
+        (Ok(s), Ok(expected)) => assert_eq!(s, expected),
+        (Err(utf8::DecodeError::Invalid { valid_prefix, invalid_sequence, .. }), Err(error)) => {
+            assert_eq!(valid_prefix.len(), error.valid_up_to());
+            assert_eq!(Some(invalid_sequence.len()), error.error_len());
+        }
+        (Err(utf8::DecodeError::Incomplete { valid_prefix, incomplete_suffix }), Err(error)) => {
+            assert_eq!(valid_prefix.len(), error.valid_up_to());
+            assert_eq!(error.error_len(), None);
+            assert_eq!(incomplete_suffix.buffered_bytes(), &input[error.valid_up_to()..]);
+        }
+        (result, expected) => panic!("{:?} != {:?}", result, expected),
+    }
+
+    // A small capacity splits code points across `fill_buf` calls.
+    let reader = BufReader::with_capacity(capacity, input);
+    let string = utf8::BufReadDecoder::read_to_string_lossy(reader).unwrap();
+    assert_eq!(string, String::from_utf8_lossy(input));
+});
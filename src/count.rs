@@ -0,0 +1,111 @@
+use core::mem;
+use super::*;
+
+/// Count the code points in `input`.
+///
+/// This counts bytes that are not continuation bytes, one machine word at a time.
+pub fn count_chars(input: &str) -> usize {
+    const WORD: usize = mem::size_of::<usize>();
+    const LOW_BITS: usize = usize::MAX / 0xFF;  // 0x0101…01
+
+    let bytes = input.as_bytes();
+    let mut words = bytes.chunks_exact(WORD);
+    let mut count = 0;
+    for word in &mut words {
+        let mut array = [0; WORD];
+        array.copy_from_slice(word);
+        let word = usize::from_ne_bytes(array);
+        // The low bit of each byte is set unless it is a continuation byte (0b10xx_xxxx)
+        let not_continuation = (!word >> 7) | (word >> 6);
+        count += (not_continuation & LOW_BITS).count_ones() as usize;
+    }
+    count + words.remainder().iter().filter(|&&byte| !is_continuation_byte(byte)).count()
+}
+
+/// Count the code points that lossy decoding of `input` would produce,
+/// including one U+FFFD replacement character per error.
+pub fn count_chars_lossy(mut input: &[u8]) -> usize {
+    let mut count = 0;
+    loop {
+        match decode(input) {
+            Ok(s) => return count + count_chars(s),
+            Err(DecodeError::Incomplete { valid_prefix, .. }) => {
+                return count + count_chars(valid_prefix) + 1
+            }
+            Err(DecodeError::Invalid { valid_prefix, remaining_input, .. }) => {
+                count += count_chars(valid_prefix) + 1;
+                input = remaining_input;
+            }
+        }
+    }
+}
+
+/// Count the U+FFFD replacement characters that lossy decoding of `input` would insert:
+/// one per error, as with `decode_lossy`. This does not allocate.
+pub fn lossy_replacement_count(mut input: &[u8]) -> usize {
+    let mut count = 0;
+    loop {
+        match decode(input) {
+            Ok(_) => return count,
+            Err(DecodeError::Incomplete { .. }) => return count + 1,
+            Err(DecodeError::Invalid { remaining_input, .. }) => {
+                count += 1;
+                input = remaining_input;
+            }
+        }
+    }
+}
+
+/// Statistics about `input`, returned by `decode_stats`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct DecodeStats {
+    /// The number of ASCII code points, one byte each.
+    pub ascii: usize,
+    pub two_byte: usize,
+    pub three_byte: usize,
+    pub four_byte: usize,
+
+    /// The number of errors, each of which lossy decoding replaces with U+FFFD.
+    /// This includes the incomplete sequence at the end, if any.
+    pub errors: usize,
+
+    /// Whether `input` ends with an incomplete sequence, which more input could complete.
+    pub incomplete_tail: bool,
+}
+
+/// Count the code points of `input` by the length of their UTF-8 sequence,
+/// and the errors, in one pass.
+pub fn decode_stats(mut input: &[u8]) -> DecodeStats {
+    fn count_valid(stats: &mut DecodeStats, valid: &str) {
+        for &byte in valid.as_bytes() {
+            match utf8_char_width(byte) {
+                1 => stats.ascii += 1,
+                2 => stats.two_byte += 1,
+                3 => stats.three_byte += 1,
+                4 => stats.four_byte += 1,
+                _ => {}  // Continuation byte
+            }
+        }
+    }
+
+    let mut stats = DecodeStats::default();
+    loop {
+        match decode(input) {
+            Ok(s) => {
+                count_valid(&mut stats, s);
+                return stats
+            }
+            Err(DecodeError::Incomplete { valid_prefix, .. }) => {
+                count_valid(&mut stats, valid_prefix);
+                stats.errors += 1;
+                stats.incomplete_tail = true;
+                return stats
+            }
+            Err(DecodeError::Invalid { valid_prefix, remaining_input, .. }) => {
+                count_valid(&mut stats, valid_prefix);
+                stats.errors += 1;
+                input = remaining_input;
+            }
+        }
+    }
+}
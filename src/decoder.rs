@@ -0,0 +1,180 @@
+use super::*;
+
+/// Like `decode`, but accept overlong sequences and decode them to their code point,
+/// such as 0xC0 0x80 to U+0000.
+///
+/// The result borrows `input` when it is valid UTF-8, but is owned if it has overlong sequences.
+/// On error, everything decoded so far is discarded.
+///
+/// **Security note:** overlong sequences are invalid UTF-8 because they allow
+/// the same text to have multiple encodings. Software that checks bytes for
+/// characters such as `/` or NUL before decoding this way can be bypassed.
+/// Only use this to interoperate with a peer known to emit overlong sequences,
+/// and validate the decoded text, not its bytes.
+#[cfg(feature = "alloc")]
+pub fn decode_relaxed(input: &[u8]) -> Result<Cow<'_, str>, OwnedDecodeError> {
+    if let Ok(s) = decode(input) {
+        return Ok(Cow::Borrowed(s))
+    }
+    let mut string = String::with_capacity(input.len());
+    let mut code_points = Decoder::builder().allow_overlong(true).build().code_points(input);
+    loop {
+        let start = input.len() - code_points.input.len();
+        let item = match code_points.next() {
+            Some(item) => item,
+            None => return Ok(Cow::Owned(string)),
+        };
+        let end = input.len() - code_points.input.len();
+        let (error_end, kind) = match item {
+            Ok(code_point) => match char::from_u32(code_point) {
+                Some(c) => {
+                    string.push(c);
+                    continue
+                }
+                // An overlong encoding of a surrogate.
+                None => (end, Utf8ErrorKind::Surrogate),
+            },
+            Err(InvalidSequence { incomplete: true, .. }) => (end, Utf8ErrorKind::Truncated),
+            Err(_) => {
+                let kind = match Utf8ErrorKind::classify(&input[start..]) {
+                    // Overlong forms are accepted: the error is in the continuation bytes.
+                    Utf8ErrorKind::Overlong => Utf8ErrorKind::InvalidContinuation,
+                    kind => kind,
+                };
+                (end, kind)
+            }
+        };
+        let mut sequence = [0; 4];
+        sequence[..error_end - start].copy_from_slice(&input[start..error_end]);
+        return Err(OwnedDecodeError {
+            valid_up_to: start,
+            sequence,
+            sequence_len: (error_end - start) as u8,
+            kind,
+        })
+    }
+}
+
+/// Configuration for a `Decoder`, with a strictness profile.
+///
+/// The default is strict UTF-8, the same as `decode`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct DecoderBuilder {
+    allow_surrogates: bool,
+    allow_overlong: bool,
+}
+
+impl DecoderBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to accept surrogate code points (U+D800 to U+DFFF)
+    /// encoded as three-byte sequences, as in WTF-8 or CESU-8.
+    ///
+    /// Unlike `decode_wtf8`, each surrogate is yielded separately
+    /// even when a lead surrogate is followed by a trail surrogate.
+    pub fn allow_surrogates(mut self, allow: bool) -> Self {
+        self.allow_surrogates = allow;
+        self
+    }
+
+    /// Whether to accept overlong sequences, which use more bytes than necessary
+    /// for a code point, such as 0xC0 0x80 for U+0000 in Java’s modified UTF-8.
+    pub fn allow_overlong(mut self, allow: bool) -> Self {
+        self.allow_overlong = allow;
+        self
+    }
+
+    pub fn build(self) -> Decoder {
+        Decoder { config: self }
+    }
+}
+
+/// A UTF-8 decoder with a configurable strictness profile. See `DecoderBuilder`.
+///
+/// Since accepted code points may not be Unicode scalar values,
+/// and overlong sequences are not the UTF-8 of their code point,
+/// this decodes to `u32` code points rather than borrowed `&str` slices.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Decoder {
+    config: DecoderBuilder,
+}
+
+impl Decoder {
+    pub fn builder() -> DecoderBuilder {
+        DecoderBuilder::new()
+    }
+
+    /// Iterate over the code points and errors of `input`.
+    ///
+    /// Errors are maximal subparts of ill-formed sequences, as with `decode`.
+    /// If `input` ends with an incomplete sequence, the last item is
+    /// an `InvalidSequence` with `incomplete` set to `true`.
+    pub fn code_points<'a>(&self, input: &'a [u8]) -> DecoderCodePoints<'a> {
+        DecoderCodePoints {
+            config: self.config,
+            input,
+        }
+    }
+
+    /// Return whether all of `input` is accepted by this decoder.
+    pub fn is_valid(&self, input: &[u8]) -> bool {
+        self.code_points(input).all(|item| item.is_ok())
+    }
+}
+
+/// The iterator returned by `Decoder::code_points`.
+#[derive(Debug, Clone)]
+pub struct DecoderCodePoints<'a> {
+    config: DecoderBuilder,
+    input: &'a [u8],
+}
+
+impl<'a> Iterator for DecoderCodePoints<'a> {
+    type Item = Result<u32, InvalidSequence<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = *self.input.first()?;
+        let allow_overlong = self.config.allow_overlong;
+        let allow_surrogates = self.config.allow_surrogates;
+        // The sequence length, and the range for the second byte.
+        let (len, second_min, second_max) = match first {
+            0x00..=0x7F => {
+                self.input = &self.input[1..];
+                return Some(Ok(first as u32))
+            }
+            0xC0..=0xC1 if allow_overlong => (2, 0x80, 0xBF),
+            0xC2..=0xDF => (2, 0x80, 0xBF),
+            0xE0 if !allow_overlong => (3, 0xA0, 0xBF),
+            0xED if !allow_surrogates => (3, 0x80, 0x9F),
+            0xE0..=0xEF => (3, 0x80, 0xBF),
+            0xF0 if !allow_overlong => (4, 0x90, 0xBF),
+            0xF0..=0xF3 => (4, 0x80, 0xBF),
+            0xF4 => (4, 0x80, 0x8F),
+            _ => return Some(Err(self.take_invalid(1, false))),
+        };
+        let mut code_point = first as u32 & (0x7F >> len);
+        for i in 1..len {
+            let byte = match self.input.get(i) {
+                Some(&byte) => byte,
+                None => return Some(Err(self.take_invalid(i, true))),
+            };
+            let (min, max) = if i == 1 { (second_min, second_max) } else { (0x80, 0xBF) };
+            if byte < min || byte > max {
+                return Some(Err(self.take_invalid(i, false)))
+            }
+            code_point = (code_point << 6) | (byte as u32 & 0x3F);
+        }
+        self.input = &self.input[len..];
+        Some(Ok(code_point))
+    }
+}
+
+impl<'a> DecoderCodePoints<'a> {
+    fn take_invalid(&mut self, len: usize, incomplete: bool) -> InvalidSequence<'a> {
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        InvalidSequence { bytes, incomplete }
+    }
+}
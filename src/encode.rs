@@ -0,0 +1,42 @@
+use core::str;
+
+/// Return the number of bytes in the UTF-8 encoding of `c`, from 1 to 4.
+#[inline]
+pub const fn char_len_utf8(c: char) -> usize {
+    match c as u32 {
+        0..=0x7F => 1,
+        0x80..=0x7FF => 2,
+        0x800..=0xFFFF => 3,
+        _ => 4,
+    }
+}
+
+/// Write the UTF-8 encoding of `c` at the start of `buffer`,
+/// and return the corresponding string slice of `buffer`.
+#[inline]
+pub fn encode_char(c: char, buffer: &mut [u8; 4]) -> &str {
+    let code_point = c as u32;
+    let len = char_len_utf8(c);
+    match len {
+        1 => {
+            buffer[0] = code_point as u8;
+        }
+        2 => {
+            buffer[0] = 0xC0 | (code_point >> 6) as u8;
+            buffer[1] = 0x80 | (code_point & 0x3F) as u8;
+        }
+        3 => {
+            buffer[0] = 0xE0 | (code_point >> 12) as u8;
+            buffer[1] = 0x80 | ((code_point >> 6) & 0x3F) as u8;
+            buffer[2] = 0x80 | (code_point & 0x3F) as u8;
+        }
+        _ => {
+            buffer[0] = 0xF0 | (code_point >> 18) as u8;
+            buffer[1] = 0x80 | ((code_point >> 12) & 0x3F) as u8;
+            buffer[2] = 0x80 | ((code_point >> 6) & 0x3F) as u8;
+            buffer[3] = 0x80 | (code_point & 0x3F) as u8;
+        }
+    }
+    // `char` excludes surrogates and values beyond U+10FFFF, so this is well-formed.
+    unsafe { str::from_utf8_unchecked(&buffer[..len]) }
+}
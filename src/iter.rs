@@ -0,0 +1,626 @@
+use super::*;
+use core::ops;
+
+/// Iterate over the maximal valid runs and the errors of `input`.
+///
+/// Each item borrows from `input`.
+/// Valid runs are never empty, and consecutive items are adjacent in `input`.
+/// If `input` ends with an incomplete sequence, the last item is
+/// an `InvalidSequence` with `incomplete` set to `true`.
+pub fn decode_pieces(input: &[u8]) -> DecodePieces<'_> {
+    DecodePieces {
+        input,
+        pending_error: None,
+    }
+}
+
+/// The iterator returned by `decode_pieces`.
+#[derive(Debug, Clone)]
+pub struct DecodePieces<'a> {
+    input: &'a [u8],
+    pending_error: Option<InvalidSequence<'a>>,
+}
+
+impl<'a> Iterator for DecodePieces<'a> {
+    type Item = Result<&'a str, InvalidSequence<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(error) = self.pending_error.take() {
+            return Some(Err(error))
+        }
+        if self.input.is_empty() {
+            return None
+        }
+        let (valid_prefix, error) = match decode(self.input) {
+            Ok(valid) => {
+                self.input = &[];
+                return Some(Ok(valid))
+            }
+            Err(DecodeError::Invalid { valid_prefix, invalid_sequence, remaining_input, .. }) => {
+                self.input = remaining_input;
+                (valid_prefix, InvalidSequence { bytes: invalid_sequence, incomplete: false })
+            }
+            Err(DecodeError::Incomplete { valid_prefix, .. }) => {
+                let bytes = &self.input[valid_prefix.len()..];
+                self.input = &[];
+                (valid_prefix, InvalidSequence { bytes, incomplete: true })
+            }
+        };
+        if valid_prefix.is_empty() {
+            Some(Err(error))
+        } else {
+            self.pending_error = Some(error);
+            Some(Ok(valid_prefix))
+        }
+    }
+}
+
+/// Iterate over alternating runs of valid and invalid bytes of `input`,
+/// keeping invalid bytes verbatim instead of replacing them.
+///
+/// Each item is a maximal valid run followed by the maximal invalid run after it,
+/// made of one or more consecutive ill-formed sequences.
+/// Only the first valid run and the last invalid run can be empty.
+/// An incomplete sequence at the end of `input` is part of the last invalid run.
+/// Concatenating all items gives back `input`.
+pub fn split_valid(input: &[u8]) -> SplitValid<'_> {
+    SplitValid { input }
+}
+
+/// The iterator returned by `split_valid`.
+#[derive(Debug, Clone)]
+pub struct SplitValid<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Iterator for SplitValid<'a> {
+    type Item = (&'a str, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.input.is_empty() {
+            return None
+        }
+        let (valid, rest) = decode_prefix(self.input);
+        let mut invalid_len = 0;
+        loop {
+            match utf8_error(&rest[invalid_len..]) {
+                Some((0, Some(error_len))) => invalid_len += error_len,
+                Some((0, None)) => invalid_len = rest.len(),
+                _ => break,
+            }
+        }
+        let (invalid, remaining) = rest.split_at(invalid_len);
+        self.input = remaining;
+        Some((valid, invalid))
+    }
+}
+
+/// Iterate over the lines of `input`, decoded lossily.
+///
+/// Lines are split like with `str::lines`: on `\n` or `\r\n`, which are not included,
+/// and a final line ending does not start an empty line.
+/// A line borrows `input` when it is valid UTF-8,
+/// and is owned with errors replaced by U+FFFD otherwise.
+///
+/// ```rust
+/// let lines: Vec<_> = utf8::lossy_lines(b"caf\xC3\xA9\r\n\xFF\n").collect();
+/// assert_eq!(lines, ["café", "\u{FFFD}"]);
+/// ```
+#[cfg(feature = "alloc")]
+pub fn lossy_lines(input: &[u8]) -> LossyLines<'_> {
+    LossyLines { input }
+}
+
+/// The iterator returned by `lossy_lines`.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct LossyLines<'a> {
+    input: &'a [u8],
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> Iterator for LossyLines<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.input.is_empty() {
+            return None
+        }
+        // 0x0A is never part of a multi-byte sequence, so this does not split a code point.
+        let line = match self.input.iter().position(|&byte| byte == b'\n') {
+            Some(position) => {
+                let line = &self.input[..position];
+                self.input = &self.input[position + 1..];
+                if let Some((&b'\r', before)) = line.split_last() {
+                    before
+                } else {
+                    line
+                }
+            }
+            None => {
+                let line = self.input;
+                self.input = &[];
+                line
+            }
+        };
+        Some(decode_lossy(line))
+    }
+}
+
+/// Iterate over the byte offsets of `input` where it can be split without splitting a code point,
+/// in increasing order from 0 to `input.len()` included.
+///
+/// With `skip_before_combining_marks`, offsets just before a combining mark are skipped,
+/// so that splits do not separate most accents from their base character.
+///
+/// ```rust
+/// let points: Vec<usize> = utf8::split_points("e\u{301}a").collect();
+/// assert_eq!(points, [0, 1, 3, 4]);
+/// let points: Vec<usize> = utf8::split_points("e\u{301}a").skip_before_combining_marks(true)
+///     .collect();
+/// assert_eq!(points, [0, 3, 4]);
+/// ```
+pub fn split_points(input: &str) -> SplitPoints<'_> {
+    SplitPoints {
+        chars: input.char_indices(),
+        len: input.len(),
+        end_returned: false,
+        skip_before_combining_marks: false,
+    }
+}
+
+/// The iterator returned by `split_points`.
+#[derive(Debug, Clone)]
+pub struct SplitPoints<'a> {
+    chars: str::CharIndices<'a>,
+    len: usize,
+    end_returned: bool,
+    skip_before_combining_marks: bool,
+}
+
+impl<'a> SplitPoints<'a> {
+    /// Whether to skip offsets immediately before a combining mark.
+    ///
+    /// This is a heuristic, not the grapheme cluster boundaries of
+    /// [UAX #29](https://www.unicode.org/reports/tr29/).
+    /// Combining marks are the code points of the combining blocks shared by all scripts:
+    /// U+0300 to U+036F, U+1AB0 to U+1AFF, U+1DC0 to U+1DFF, U+20D0 to U+20FF,
+    /// and U+FE20 to U+FE2F. Marks of specific scripts, such as Devanagari vowel signs,
+    /// and sequences joined with U+200D are not kept together.
+    ///
+    /// This is disabled by default. The end of the input is always a split point.
+    pub fn skip_before_combining_marks(mut self, skip: bool) -> Self {
+        self.skip_before_combining_marks = skip;
+        self
+    }
+}
+
+impl<'a> Iterator for SplitPoints<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        for (offset, c) in &mut self.chars {
+            if !(self.skip_before_combining_marks && is_combining_mark(c)) {
+                return Some(offset)
+            }
+        }
+        if self.end_returned {
+            return None
+        }
+        self.end_returned = true;
+        Some(self.len)
+    }
+}
+
+/// Whether `c` is in one of the generic combining mark blocks, for `SplitPoints`.
+fn is_combining_mark(c: char) -> bool {
+    matches!(c, '\u{300}'..='\u{36F}' | '\u{1AB0}'..='\u{1AFF}' | '\u{1DC0}'..='\u{1DFF}' |
+                '\u{20D0}'..='\u{20FF}' | '\u{FE20}'..='\u{FE2F}')
+}
+
+/// Iterate over the code points and errors of `input`, with their byte offsets in `input`.
+///
+/// Errors are maximal subparts of ill-formed sequences, as with `decode`.
+pub fn char_indices(input: &[u8]) -> CharIndices<'_> {
+    CharIndices {
+        pieces: decode_pieces(input),
+        offset: 0,
+        chars: "".char_indices(),
+        chars_offset: 0,
+    }
+}
+
+/// Like `char_indices`, but replace each error with U+FFFD at its byte offset.
+pub fn char_indices_lossy(input: &[u8]) -> CharIndicesLossy<'_> {
+    CharIndicesLossy(char_indices(input))
+}
+
+/// The iterator returned by `char_indices`.
+#[derive(Debug, Clone)]
+pub struct CharIndices<'a> {
+    pieces: DecodePieces<'a>,
+    /// The offset of the next piece.
+    offset: usize,
+    chars: str::CharIndices<'a>,
+    /// The offset of the valid run being iterated by `chars`.
+    chars_offset: usize,
+}
+
+impl<'a> Iterator for CharIndices<'a> {
+    type Item = Result<(usize, char), (usize, InvalidSequence<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((index, c)) = self.chars.next() {
+            return Some(Ok((self.chars_offset + index, c)))
+        }
+        let offset = self.offset;
+        match self.pieces.next()? {
+            Ok(valid) => {
+                self.offset += valid.len();
+                self.chars = valid.char_indices();
+                self.chars_offset = offset;
+                self.next()
+            }
+            Err(invalid_sequence) => {
+                self.offset += invalid_sequence.bytes.len();
+                Some(Err((offset, invalid_sequence)))
+            }
+        }
+    }
+}
+
+/// The iterator returned by `char_indices_lossy`.
+#[derive(Debug, Clone)]
+pub struct CharIndicesLossy<'a>(CharIndices<'a>);
+
+impl<'a> Iterator for CharIndicesLossy<'a> {
+    type Item = (usize, char);
+
+    fn next(&mut self) -> Option<(usize, char)> {
+        match self.0.next()? {
+            Ok(item) => Some(item),
+            Err((offset, _)) => Some((offset, REPLACEMENT_CHAR)),
+        }
+    }
+}
+
+/// Decode bytes pulled from an iterator, lossily, replacing errors with U+FFFD.
+///
+/// There is one replacement character per maximal subpart of an ill-formed sequence,
+/// the same as with `decode_lossy`, including for a sequence truncated by the end.
+pub fn decode_iter<I: IntoIterator<Item = u8>>(iter: I) -> DecodeIter<I::IntoIter> {
+    DecodeIter {
+        bytes: iter.into_iter(),
+        pending_byte: None,
+    }
+}
+
+/// The iterator returned by `decode_iter`.
+#[derive(Debug, Clone)]
+pub struct DecodeIter<I: Iterator<Item = u8>> {
+    bytes: I,
+    /// A byte that ended an ill-formed sequence and starts the next one.
+    pending_byte: Option<u8>,
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecodeIter<I> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let first = match self.pending_byte.take() {
+            Some(byte) => byte,
+            None => self.bytes.next()?,
+        };
+        // The sequence length, and the range for the second byte.
+        let (len, second_min, second_max) = match first {
+            0x00..=0x7F => return Some(first as char),
+            0xC2..=0xDF => (2, 0x80, 0xBF),
+            0xE0 => (3, 0xA0, 0xBF),
+            0xED => (3, 0x80, 0x9F),
+            0xE1..=0xEF => (3, 0x80, 0xBF),
+            0xF0 => (4, 0x90, 0xBF),
+            0xF1..=0xF3 => (4, 0x80, 0xBF),
+            0xF4 => (4, 0x80, 0x8F),
+            _ => return Some(REPLACEMENT_CHAR),
+        };
+        let mut code_point = first as u32 & (0x7F >> len);
+        for i in 1..len {
+            let byte = match self.bytes.next() {
+                Some(byte) => byte,
+                None => return Some(REPLACEMENT_CHAR),
+            };
+            let (min, max) = if i == 1 { (second_min, second_max) } else { (0x80, 0xBF) };
+            if byte < min || byte > max {
+                self.pending_byte = Some(byte);
+                return Some(REPLACEMENT_CHAR)
+            }
+            code_point = (code_point << 6) | (byte as u32 & 0x3F);
+        }
+        Some(unsafe { char::from_u32_unchecked(code_point) })
+    }
+}
+
+/// One piece of decoded input, yielded by `feed_pieces`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DecodedPiece<'a> {
+    /// A non-empty run of valid input, borrowed from the chunk.
+    InputSlice(&'a str),
+
+    /// A code point whose bytes were split between previous chunks and this one,
+    /// copied inline since it is not contiguous in either chunk.
+    Stitched(ArrayStr),
+
+    /// An ill-formed byte sequence. In lossy decoding, insert U+FFFD here.
+    Replacement,
+}
+
+/// A string of one code point, stored inline in 4 bytes.
+///
+/// Use it as a `&str` through `Deref`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ArrayStr {
+    bytes: [u8; 4],
+    len: u8,
+}
+
+impl ArrayStr {
+    fn new(s: &str) -> Self {
+        let mut bytes = [0; 4];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        ArrayStr { bytes, len: s.len() as u8 }
+    }
+
+    /// The stored string slice.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        unsafe { str::from_utf8_unchecked(&self.bytes[..self.len as usize]) }
+    }
+}
+
+impl ops::Deref for ArrayStr {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<char> for ArrayStr {
+    #[inline]
+    fn from(c: char) -> Self {
+        let mut bytes = [0; 4];
+        let len = c.encode_utf8(&mut bytes).len() as u8;
+        ArrayStr { bytes, len }
+    }
+}
+
+impl fmt::Debug for ArrayStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// Decode one chunk of a stream, continuing from the code point buffered in `incomplete`.
+///
+/// If the chunk ends with an incomplete sequence, it is stored in `incomplete`
+/// instead of being yielded, to be completed by the next chunk.
+/// At the end of the stream, a non-empty `incomplete` is one more error.
+///
+/// When `incomplete` is empty and `input` is non-empty and entirely valid UTF-8,
+/// this yields exactly one `DecodedPiece::InputSlice` with all of `input`.
+pub fn feed_pieces<'i, 'a>(incomplete: &'i mut Incomplete, input: &'a [u8])
+                           -> FeedPieces<'i, 'a> {
+    FeedPieces {
+        incomplete,
+        input,
+        pending_replacement: false,
+    }
+}
+
+/// The iterator returned by `feed_pieces`.
+#[derive(Debug)]
+pub struct FeedPieces<'i, 'a> {
+    incomplete: &'i mut Incomplete,
+    input: &'a [u8],
+    pending_replacement: bool,
+}
+
+impl<'i, 'a> Iterator for FeedPieces<'i, 'a> {
+    type Item = DecodedPiece<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.incomplete.is_empty() {
+            // Only at the start: this is also where a trailing incomplete sequence ends up,
+            // but `self.input` is then empty and `try_complete` returns `None`.
+            // Only give `try_complete` the bytes of this one code point.
+            let needed = cmp::min(self.incomplete.bytes_needed(), self.input.len());
+            let (piece, remaining_input) = match self.incomplete.try_complete(&self.input[..needed]) {
+                Some((Ok(s), remaining_input)) => {
+                    (DecodedPiece::Stitched(ArrayStr::new(s)), remaining_input)
+                }
+                Some((Err(_), remaining_input)) => (DecodedPiece::Replacement, remaining_input),
+                None => {
+                    self.input = &[];
+                    return None
+                }
+            };
+            self.input = &self.input[needed - remaining_input.len()..];
+            return Some(piece)
+        }
+        if self.pending_replacement {
+            self.pending_replacement = false;
+            return Some(DecodedPiece::Replacement)
+        }
+        if self.input.is_empty() {
+            return None
+        }
+        match decode(self.input) {
+            Ok(valid) => {
+                self.input = &[];
+                Some(DecodedPiece::InputSlice(valid))
+            }
+            Err(DecodeError::Invalid { valid_prefix, remaining_input, .. }) => {
+                self.input = remaining_input;
+                if valid_prefix.is_empty() {
+                    Some(DecodedPiece::Replacement)
+                } else {
+                    self.pending_replacement = true;
+                    Some(DecodedPiece::InputSlice(valid_prefix))
+                }
+            }
+            Err(DecodeError::Incomplete { valid_prefix, incomplete_suffix }) => {
+                *self.incomplete = incomplete_suffix;
+                self.input = &[];
+                if valid_prefix.is_empty() {
+                    None
+                } else {
+                    Some(DecodedPiece::InputSlice(valid_prefix))
+                }
+            }
+        }
+    }
+}
+
+/// A pull-based incremental decoder: call `feed` with each chunk of input
+/// and iterate over the pieces it returns, across chunk boundaries without a callback.
+///
+/// A code point split between chunks is copied to a 4-byte scratch buffer in the decoder,
+/// so the pieces returned by `feed` borrow both the decoder and the chunk:
+/// they need to be handled or copied before `feed` or `finish` can be called again.
+///
+/// ```rust
+/// let mut decoder = utf8::IncrementalDecoder::new();
+/// let mut string = String::new();
+/// for chunk in &[&b"caf\xC3"[..], &b"\xA9 \xFF"[..]] {
+///     for piece in decoder.feed(chunk) {
+///         string.push_str(piece.unwrap_or(utf8::REPLACEMENT_CHARACTER))
+///     }
+/// }
+/// assert!(decoder.finish().is_none());
+/// assert_eq!(string, "café \u{FFFD}");
+/// ```
+#[derive(Debug, Clone)]
+pub struct IncrementalDecoder {
+    incomplete: Incomplete,
+    scratch: [u8; 4],
+}
+
+impl IncrementalDecoder {
+    pub fn new() -> Self {
+        IncrementalDecoder {
+            incomplete: Incomplete::empty(),
+            scratch: [0; 4],
+        }
+    }
+
+    /// Whether the decoder holds the start of a code point from previous input,
+    /// waiting for more input to complete it.
+    /// If so, `finish` returns it as an error.
+    #[inline]
+    pub fn has_incomplete(&self) -> bool {
+        !self.incomplete.is_empty()
+    }
+
+    /// Decode one chunk of input.
+    ///
+    /// Items are the same as with `decode_pieces`, except that an incomplete sequence
+    /// at the end of the chunk is kept in the decoder instead of being returned,
+    /// and that the first item can be a code point (or error) stitched from previous chunks.
+    /// The iterator needs to be exhausted for that incomplete sequence to be kept.
+    pub fn feed<'s>(&'s mut self, mut input: &'s [u8]) -> IncrementalPieces<'s> {
+        let mut stitched = None;
+        let input_len = input.len();
+        if !self.incomplete.is_empty() {
+            // Only give `try_complete` the bytes of this one code point.
+            let needed = cmp::min(self.incomplete.bytes_needed(), input.len());
+            match self.incomplete.try_complete(&input[..needed]) {
+                Some((result, remaining_input)) => {
+                    let bytes = match result {
+                        Ok(s) => s.as_bytes(),
+                        Err(bytes) => bytes,
+                    };
+                    self.scratch[..bytes.len()].copy_from_slice(bytes);
+                    stitched = Some((bytes.len(), result.is_ok()));
+                    input = &input[needed - remaining_input.len()..];
+                }
+                None => input = &[],
+            }
+        }
+        let scratch = &self.scratch;
+        IncrementalPieces {
+            stitched: stitched.map(|(len, is_ok)| {
+                let bytes = &scratch[..len];
+                if is_ok {
+                    Ok(unsafe { str::from_utf8_unchecked(bytes) })
+                } else {
+                    Err(InvalidSequence { bytes, incomplete: false })
+                }
+            }),
+            consumed: input_len - input.len(),
+            pieces: decode_pieces(input),
+            incomplete: &mut self.incomplete,
+        }
+    }
+
+    /// Signal the end of the input.
+    /// Return the incomplete sequence left at the end of the last chunk, if any,
+    /// which is an error.
+    pub fn finish(&mut self) -> Option<InvalidSequence<'_>> {
+        if self.incomplete.is_empty() {
+            return None
+        }
+        Some(InvalidSequence { bytes: self.incomplete.take_buffer(), incomplete: true })
+    }
+}
+
+impl Default for IncrementalDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The iterator returned by `IncrementalDecoder::feed`.
+#[derive(Debug)]
+pub struct IncrementalPieces<'s> {
+    stitched: Option<Result<&'s str, InvalidSequence<'s>>>,
+    consumed: usize,
+    pieces: DecodePieces<'s>,
+    incomplete: &'s mut Incomplete,
+}
+
+impl<'s> IncrementalPieces<'s> {
+    /// The number of bytes of the chunk given to `feed` that were consumed so far.
+    ///
+    /// Bytes completing a code point from previous chunks are consumed by `feed` itself,
+    /// others as the items containing them are returned.
+    /// A trailing incomplete sequence is consumed into the decoder when the iterator
+    /// is exhausted, so this is then the whole chunk.
+    ///
+    /// If iteration stops early, the rest of the chunk can be fed again later.
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+}
+
+impl<'s> Iterator for IncrementalPieces<'s> {
+    type Item = Result<&'s str, InvalidSequence<'s>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(stitched) = self.stitched.take() {
+            return Some(stitched)
+        }
+        let item = self.pieces.next()?;
+        self.consumed += match item {
+            Ok(s) => s.len(),
+            Err(ref invalid_sequence) => invalid_sequence.bytes.len(),
+        };
+        match item {
+            Err(InvalidSequence { bytes, incomplete: true }) => {
+                *self.incomplete = Incomplete::new(bytes);
+                None
+            }
+            item => Some(item),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+#[cfg(feature = "alloc")]
+use alloc::borrow::Cow;
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+use core::str;
+
+/// Decode `input` as Latin-1 (ISO-8859-1), where each byte maps to the code point of the same value.
+///
+/// Every byte sequence is valid Latin-1, so there is no error case.
+/// This returns `Cow::Borrowed` without allocating when `input` is entirely ASCII,
+/// and `Cow::Owned` otherwise.
+#[cfg(feature = "alloc")]
+pub fn decode_latin1(input: &[u8]) -> Cow<'_, str> {
+    let ascii_len = ascii_len(input);
+    if ascii_len == input.len() {
+        return Cow::Borrowed(unsafe { str::from_utf8_unchecked(input) })
+    }
+    // Each non-ASCII byte takes two bytes in UTF-8.
+    let mut string = String::with_capacity(input.len() + (input.len() - ascii_len));
+    Latin1Decoder::new(|s| string.push_str(s)).feed(input);
+    Cow::Owned(string)
+}
+
+/// A push-based decoder for Latin-1 (ISO-8859-1), with the same shape as `LossyDecoder`.
+///
+/// Users “push” bytes into the decoder, which in turn “pushes” `&str` slices into a callback.
+/// Every byte sequence is valid Latin-1 and each byte is one code point,
+/// so unlike `LossyDecoder` there is no error case and no state kept between chunks.
+#[derive(Clone)]
+pub struct Latin1Decoder<F: FnMut(&str)> {
+    push_str: F,
+}
+
+impl<F: FnMut(&str)> Latin1Decoder<F> {
+    /// Create a new decoder from a callback.
+    #[inline]
+    pub fn new(push_str: F) -> Self {
+        Latin1Decoder { push_str }
+    }
+
+    /// Feed one chunk of input into the decoder.
+    ///
+    /// The callback is called with runs of ASCII input, borrowed without copying,
+    /// and with runs of non-ASCII input transcoded through a stack buffer.
+    /// It is never called with an empty string.
+    pub fn feed(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            let ascii_len = ascii_len(input);
+            if ascii_len > 0 {
+                (self.push_str)(unsafe { str::from_utf8_unchecked(&input[..ascii_len]) });
+                input = &input[ascii_len..];
+                continue
+            }
+            let mut buffer = [0_u8; 256];
+            let mut len = 0;
+            while let Some(&byte) = input.first() {
+                // Each non-ASCII byte takes two bytes in UTF-8.
+                if byte < 0x80 || buffer.len() - len < 2 {
+                    break
+                }
+                len += char::from(byte).encode_utf8(&mut buffer[len..]).len();
+                input = &input[1..];
+            }
+            (self.push_str)(unsafe { str::from_utf8_unchecked(&buffer[..len]) });
+        }
+    }
+}
+
+fn ascii_len(input: &[u8]) -> usize {
+    input.iter().position(|&byte| byte >= 0x80).unwrap_or(input.len())
+}
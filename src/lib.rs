@@ -1,24 +1,190 @@
+//! Incremental, zero-copy UTF-8 decoding with error handling.
+//!
+//! Every decoder in this crate is built on `decode`, which returns the valid prefix
+//! of its input borrowed as `&str`, and a `DecodeError` for what follows.
+//! `DecodeError::Incomplete` holds a code point split at the end of a chunk in an `Incomplete`,
+//! which the next chunk completes.
+//!
+//! Which API to use depends on how input arrives, and where output goes:
+//!
+//! * One buffer: `decode`, `decode_lossy`, `decode_lossy_into`, `write_lossy`,
+//!   `decode_lossy_to_writer`, `decode_lossy_with_progress`, `lossy_lines`,
+//!   or iterate with `decode_pieces`, `split_valid`, `char_indices`, or `decode_one`.
+//!   `split_points` finds where decoded text can be split.
+//!   `decode_positions` and `is_valid_utf8` only check validity.
+//! * Chunks, with output pushed to a callback: `LossyDecoder` for `&str`,
+//!   `CharDecoder` for `char`, `Utf8ToUtf16` for UTF-16,
+//!   or `WriteDecoder` as an `io::Write` sink.
+//! * Chunks, with output appended to a `String`: `OwningDecoder`.
+//! * Chunks, with output pulled as `DecodedPiece`s: `IncrementalDecoder` or `feed_pieces`.
+//! * Readers: `BufReadDecoder`, `AsyncUtf8Stream` (with the `futures` feature),
+//!   or `TokioUtf8Decoder` (with the `tokio` feature).
+//! * Variants of UTF-8 that accept surrogates or overlong sequences:
+//!   `Decoder`, `decode_relaxed`, `decode_wtf8`, and the `modified_utf8` module.
+//!
+//! The `prelude` module re-exports the names for typical decoding tasks.
+//!
+//! Only APIs that return or append to a `String`, `Vec`, or `Cow` allocate,
+//! and they require the `alloc` feature. Decoders keep a code point split across chunks
+//! in `Incomplete`, an inline buffer of 4 bytes, so they never allocate.
+//! There are two exceptions, both in readers:
+//! `MinFill`, used by `BufReadDecoder::with_min_fill`, accumulates input in a `Vec`,
+//! and `BufReadDecoder::next_lossy_coalesced` merges chunks in a `String`.
+
+#![no_std]
+
+#[cfg(feature = "std")]
+extern crate std;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "bytes")]
+extern crate bytes;
+#[cfg(feature = "futures")]
+extern crate futures_core;
+#[cfg(feature = "futures")]
+extern crate futures_io;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+
+mod count;
+mod decoder;
+mod encode;
+mod iter;
+mod latin1;
 mod lossy;
+#[cfg(feature = "alloc")]
+pub mod modified_utf8;
+pub mod prelude;
+#[cfg(feature = "std")]
 mod read;
+#[cfg(feature = "serde")]
+mod serde_impls;
+#[cfg(feature = "futures")]
+mod stream;
+#[cfg(feature = "tokio")]
+mod tokio_decoder;
+mod utf16;
+#[cfg(feature = "std")]
+mod write;
+mod wtf8;
 
-pub use lossy::LossyDecoder;
-pub use read::{BufReadDecoder, BufReadDecoderError};
+pub use count::{count_chars, count_chars_lossy, decode_stats, lossy_replacement_count, DecodeStats};
+#[cfg(feature = "alloc")]
+pub use decoder::decode_relaxed;
+pub use decoder::{Decoder, DecoderBuilder, DecoderCodePoints};
+pub use encode::{char_len_utf8, encode_char};
+pub use iter::{char_indices, char_indices_lossy, CharIndices, CharIndicesLossy};
+pub use iter::{decode_iter, DecodeIter};
+pub use iter::{decode_pieces, feed_pieces, ArrayStr, DecodePieces, DecodedPiece, FeedPieces};
+pub use iter::{IncrementalDecoder, IncrementalPieces};
+pub use iter::{split_points, split_valid, SplitPoints, SplitValid};
+#[cfg(feature = "alloc")]
+pub use iter::{lossy_lines, LossyLines};
+#[cfg(feature = "alloc")]
+pub use latin1::decode_latin1;
+pub use latin1::Latin1Decoder;
+pub use lossy::{CharDecoder, KindReplacement, LossyDecoder, Replacement, ReplacementPolicy};
+#[cfg(feature = "alloc")]
+pub use lossy::OwningDecoder;
+#[cfg(feature = "std")]
+pub use read::{BufReadDecoder, BufReadDecoderError, MinFill, Utf8Source};
+#[cfg(feature = "serde")]
+pub use serde_impls::LossyString;
+#[cfg(feature = "futures")]
+pub use stream::AsyncUtf8Stream;
+#[cfg(feature = "tokio")]
+pub use tokio_decoder::{NextLossy, TokioUtf8Decoder};
+pub use utf16::{Utf16LossyDecoder, Utf8ToUtf16};
+#[cfg(feature = "std")]
+pub use write::{decode_lossy_to_writer, WriteDecoder};
+pub use wtf8::{decode_wtf8, Wtf8, Wtf8CodePoints, Wtf8DecodeError};
 
-use std::cmp;
-use std::error::Error;
-use std::fmt;
-use std::str;
+#[cfg(feature = "alloc")]
+use alloc::borrow::Cow;
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::cmp;
+use core::fmt;
+use core::str;
 
 /// The replacement character, U+FFFD. In lossy decoding, insert it for every decoding error.
-pub const REPLACEMENT_CHARACTER: &'static str = "\u{FFFD}";
+pub const REPLACEMENT_CHARACTER: &str = "\u{FFFD}";
+
+/// The replacement character U+FFFD as a `char`, for APIs that work with `char`s.
+/// Use `REPLACEMENT_CHARACTER` for those that work with `&str`.
+pub const REPLACEMENT_CHAR: char = '\u{FFFD}';
+
+/// The UTF-8 encoding of U+FEFF, used as a byte order mark.
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Remove a byte order mark (U+FEFF, bytes 0xEF 0xBB 0xBF) from the start of `input`, if any.
+pub fn strip_bom(input: &[u8]) -> &[u8] {
+    if input.starts_with(UTF8_BOM) {
+        &input[UTF8_BOM.len()..]
+    } else {
+        input
+    }
+}
+
+/// A byte order mark, identifying the encoding of a stream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Bom {
+    /// 0xEF 0xBB 0xBF
+    Utf8,
+    /// 0xFF 0xFE
+    Utf16Le,
+    /// 0xFE 0xFF
+    Utf16Be,
+    /// 0xFF 0xFE 0x00 0x00
+    Utf32Le,
+    /// 0x00 0x00 0xFE 0xFF
+    Utf32Be,
+}
+
+impl Bom {
+    /// The bytes of this byte order mark, to skip `as_bytes().len()` bytes of input.
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Bom::Utf8 => UTF8_BOM,
+            Bom::Utf16Le => b"\xFF\xFE",
+            Bom::Utf16Be => b"\xFE\xFF",
+            Bom::Utf32Le => b"\xFF\xFE\x00\x00",
+            Bom::Utf32Be => b"\x00\x00\xFE\xFF",
+        }
+    }
+}
+
+/// Find which byte order mark `input` starts with, if any, looking at up to 4 bytes.
+///
+/// Input starting with a UTF-16 or UTF-32 byte order mark is not UTF-8,
+/// and should be decoded with another decoder.
+/// 0xFF 0xFE 0x00 0x00 is taken to be UTF-32LE, not UTF-16LE followed by U+0000.
+pub fn sniff_bom(input: &[u8]) -> Option<Bom> {
+    match input {
+        [0xEF, 0xBB, 0xBF, ..] => Some(Bom::Utf8),
+        [0xFF, 0xFE, 0x00, 0x00, ..] => Some(Bom::Utf32Le),
+        [0x00, 0x00, 0xFE, 0xFF, ..] => Some(Bom::Utf32Be),
+        [0xFF, 0xFE, ..] => Some(Bom::Utf16Le),
+        [0xFE, 0xFF, ..] => Some(Bom::Utf16Be),
+        _ => None,
+    }
+}
 
-#[derive(Debug, Copy, Clone)]
+/// `Debug` shows the lengths of `valid_prefix` and `remaining_input` rather than their contents,
+/// and invalid bytes in hexadecimal.
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[must_use]
 pub enum DecodeError<'a> {
     /// In lossy decoding insert `valid_prefix`, then `"\u{FFFD}"`,
     /// then call `decode()` again with `remaining_input`.
     Invalid {
         valid_prefix: &'a str,
         invalid_sequence: &'a [u8],
+        kind: Utf8ErrorKind,
         remaining_input: &'a [u8],
     },
 
@@ -36,13 +202,15 @@ impl<'a> fmt::Display for DecodeError<'a> {
             DecodeError::Invalid {
                 valid_prefix,
                 invalid_sequence,
+                kind,
                 remaining_input,
             } => write!(
                 f,
-                "found invalid byte sequence {invalid_sequence:02x?} after \
+                "found invalid byte sequence {invalid_sequence:02x?} ({kind}) after \
                  {valid_byte_count} valid bytes, followed by {unprocessed_byte_count} more \
                  unprocessed bytes",
                 invalid_sequence = invalid_sequence,
+                kind = kind,
                 valid_byte_count = valid_prefix.len(),
                 unprocessed_byte_count = remaining_input.len()
             ),
@@ -53,52 +221,838 @@ impl<'a> fmt::Display for DecodeError<'a> {
                 f,
                 "found incomplete byte sequence {incomplete_suffix:02x?} after \
                  {valid_byte_count} bytes",
-                incomplete_suffix = incomplete_suffix,
+                incomplete_suffix = incomplete_suffix.buffered_bytes(),
                 valid_byte_count = valid_prefix.len()
             ),
         }
     }
 }
 
-impl<'a> Error for DecodeError<'a> {}
+impl<'a> fmt::Debug for DecodeError<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Invalid { valid_prefix, invalid_sequence, kind, remaining_input } => {
+                f.debug_struct("Invalid")
+                    .field("valid_prefix_len", &valid_prefix.len())
+                    .field("invalid_sequence", &HexBytes(invalid_sequence))
+                    .field("kind", &kind)
+                    .field("remaining_input_len", &remaining_input.len())
+                    .finish()
+            }
+            DecodeError::Incomplete { valid_prefix, ref incomplete_suffix } => {
+                f.debug_struct("Incomplete")
+                    .field("valid_prefix_len", &valid_prefix.len())
+                    .field("incomplete_suffix", incomplete_suffix)
+                    .finish()
+            }
+        }
+    }
+}
+
+/// Formats bytes as a list in hexadecimal in `Debug` impls, such as `[e2, 82]`.
+struct HexBytes<'a>(&'a [u8]);
+
+impl<'a> fmt::Debug for HexBytes<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x?}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a> std::error::Error for DecodeError<'a> {}
+
+impl<'a> DecodeError<'a> {
+    /// The input before the error, which is valid UTF-8.
+    pub fn valid_prefix(&self) -> &'a str {
+        match *self {
+            DecodeError::Invalid { valid_prefix, .. } |
+            DecodeError::Incomplete { valid_prefix, .. } => valid_prefix,
+        }
+    }
+
+    /// The length of the valid prefix, which is the byte offset of the error in the input.
+    pub fn valid_up_to(&self) -> usize {
+        self.valid_prefix().len()
+    }
+
+    /// The input after the invalid sequence, to be decoded next.
+    /// Empty for `DecodeError::Incomplete`, whose suffix is buffered in `incomplete_suffix`.
+    pub fn remaining_input(&self) -> &'a [u8] {
+        match *self {
+            DecodeError::Invalid { remaining_input, .. } => remaining_input,
+            DecodeError::Incomplete { .. } => &[],
+        }
+    }
+
+    /// Copy what is needed to describe this error into a value that does not borrow the input,
+    /// so that it can be returned from a function that decoded a local buffer.
+    pub fn into_owned(self) -> OwnedDecodeError {
+        let (valid_prefix, sequence, kind) = match self {
+            DecodeError::Invalid { valid_prefix, invalid_sequence, kind, .. } => {
+                (valid_prefix, invalid_sequence, kind)
+            }
+            DecodeError::Incomplete { valid_prefix, ref incomplete_suffix } => {
+                (valid_prefix, incomplete_suffix.buffered_bytes(), Utf8ErrorKind::Truncated)
+            }
+        };
+        let mut buffer = [0; 4];
+        buffer[..sequence.len()].copy_from_slice(sequence);
+        OwnedDecodeError {
+            valid_up_to: valid_prefix.len(),
+            sequence: buffer,
+            sequence_len: sequence.len() as u8,
+            kind,
+        }
+    }
+
+    /// Return `(valid_up_to, error_len)` with the same meaning as
+    /// `Utf8Error::valid_up_to` and `Utf8Error::error_len` for the same input:
+    /// `error_len` is `None` for an incomplete sequence at the end of the input.
+    pub fn as_std_like(&self) -> (usize, Option<usize>) {
+        let error_len = match *self {
+            DecodeError::Invalid { invalid_sequence, .. } => Some(invalid_sequence.len()),
+            DecodeError::Incomplete { .. } => None,
+        };
+        (self.valid_up_to(), error_len)
+    }
+}
+
+/// An owned version of `DecodeError`, returned by `DecodeError::into_owned`.
+///
+/// The ill-formed sequence is at most 3 bytes, and is stored inline.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[must_use]
+pub struct OwnedDecodeError {
+    valid_up_to: usize,
+    sequence: [u8; 4],
+    sequence_len: u8,
+    kind: Utf8ErrorKind,
+}
+
+impl OwnedDecodeError {
+    /// The length of the valid prefix, which is the byte offset of the error in the input.
+    pub fn valid_up_to(&self) -> usize {
+        self.valid_up_to
+    }
+
+    /// The bytes of the invalid sequence, or of the incomplete sequence at the end.
+    pub fn invalid_sequence(&self) -> &[u8] {
+        &self.sequence[..self.sequence_len as usize]
+    }
+
+    /// `Utf8ErrorKind::Truncated` for `DecodeError::Incomplete`.
+    pub fn kind(&self) -> Utf8ErrorKind {
+        self.kind
+    }
+
+    /// Whether this was `DecodeError::Incomplete`.
+    pub fn is_incomplete(&self) -> bool {
+        self.kind == Utf8ErrorKind::Truncated
+    }
+}
+
+impl fmt::Display for OwnedDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "found invalid byte sequence {:02x?} ({}) after {} valid bytes",
+               self.invalid_sequence(), self.kind, self.valid_up_to)
+    }
+}
+
+impl fmt::Debug for OwnedDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OwnedDecodeError")
+            .field("valid_up_to", &self.valid_up_to)
+            .field("invalid_sequence", &HexBytes(self.invalid_sequence()))
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for OwnedDecodeError {}
+
+/// Why a byte sequence is not well-formed UTF-8.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Utf8ErrorKind {
+    /// A byte that can never occur in UTF-8: 0xF8 to 0xFF.
+    InvalidStartByte,
+
+    /// A continuation byte (0x80 to 0xBF) not preceded by a start byte.
+    UnexpectedContinuation,
+
+    /// An encoding longer than necessary for its code point,
+    /// such as 0xC0 0x80 for U+0000.
+    Overlong,
+
+    /// An encoding of a surrogate code point, U+D800 to U+DFFF.
+    Surrogate,
+
+    /// An encoding of a code point beyond U+10FFFF.
+    CodepointTooLarge,
+
+    /// A start byte followed by something other than the expected continuation byte.
+    InvalidContinuation,
+
+    /// A sequence cut off by the end of the input.
+    /// `decode` reports these as `DecodeError::Incomplete` instead,
+    /// and so does `decode_frame` unless at the end of the input.
+    Truncated,
+}
+
+impl fmt::Display for Utf8ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Utf8ErrorKind::InvalidStartByte => "invalid start byte",
+            Utf8ErrorKind::UnexpectedContinuation => "unexpected continuation byte",
+            Utf8ErrorKind::Overlong => "overlong encoding",
+            Utf8ErrorKind::Surrogate => "encoded surrogate",
+            Utf8ErrorKind::CodepointTooLarge => "code point beyond U+10FFFF",
+            Utf8ErrorKind::InvalidContinuation => "invalid continuation byte",
+            Utf8ErrorKind::Truncated => "truncated sequence",
+        })
+    }
+}
+
+impl Utf8ErrorKind {
+    /// `bytes` starts with an invalid sequence as found by `str::from_utf8`,
+    /// and includes the byte that follows it, if any.
+    fn classify(bytes: &[u8]) -> Self {
+        let second = bytes.get(1).cloned();
+        match (bytes[0], second) {
+            (0x80..=0xBF, _) => Utf8ErrorKind::UnexpectedContinuation,
+            (0xC0..=0xC1, _) => Utf8ErrorKind::Overlong,
+            (0xE0, Some(0x80..=0x9F)) => Utf8ErrorKind::Overlong,
+            (0xED, Some(0xA0..=0xBF)) => Utf8ErrorKind::Surrogate,
+            (0xF0, Some(0x80..=0x8F)) => Utf8ErrorKind::Overlong,
+            (0xF4, Some(0x90..=0xBF)) => Utf8ErrorKind::CodepointTooLarge,
+            (0xF5..=0xF7, _) => Utf8ErrorKind::CodepointTooLarge,
+            (0xF8..=0xFF, _) => Utf8ErrorKind::InvalidStartByte,
+            _ => Utf8ErrorKind::InvalidContinuation,
+        }
+    }
+}
+
+/// An ill-formed byte sequence, borrowed from the input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InvalidSequence<'a> {
+    pub bytes: &'a [u8],
 
-#[derive(Debug, Copy, Clone)]
+    /// Whether the sequence was cut off by the end of the input.
+    /// If more input is available, it may complete the sequence for a code point.
+    pub incomplete: bool,
+}
+
+/// An owned `InvalidSequence`, for collecting errors across inputs.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InvalidSequenceBuf {
+    pub bytes: Vec<u8>,
+    pub incomplete: bool,
+}
+
+impl<'a> InvalidSequence<'a> {
+    /// The number of bytes in the sequence.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Whether the sequence has no bytes. This is never the case for sequences found in input.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> InvalidSequence<'a> {
+    /// Copy the bytes into an owned `InvalidSequenceBuf`.
+    pub fn to_buf(&self) -> InvalidSequenceBuf {
+        InvalidSequenceBuf {
+            bytes: self.bytes.into(),
+            incomplete: self.incomplete,
+        }
+    }
+
+    /// Format the bytes in lowercase hexadecimal, separated by spaces, such as `"f5 80"`.
+    pub fn to_hex(&self) -> String {
+        let mut hex = String::with_capacity(self.bytes.len() * 3);
+        for (i, byte) in self.bytes.iter().enumerate() {
+            if i > 0 {
+                hex.push(' ')
+            }
+            fmt::Write::write_fmt(&mut hex, format_args!("{:02x}", byte)).unwrap()
+        }
+        hex
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> From<InvalidSequence<'a>> for InvalidSequenceBuf {
+    fn from(invalid_sequence: InvalidSequence<'a>) -> Self {
+        invalid_sequence.to_buf()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl InvalidSequenceBuf {
+    pub fn as_invalid_sequence(&self) -> InvalidSequence<'_> {
+        InvalidSequence {
+            bytes: &self.bytes,
+            incomplete: self.incomplete,
+        }
+    }
+}
+
+/// The bytes of a code point split across input chunks, buffered until it can be completed.
+///
+/// `try_complete` works with borrowed string slices.
+/// `try_complete_offsets` and `take_buffer` are the lower-level equivalent
+/// used by `BufReadDecoder`, for reader loops that need to know
+/// how many bytes of a buffer to consume before looking at the result.
+///
+/// `Debug` shows the buffered bytes in hexadecimal, not the rest of `buffer`.
+#[derive(Copy, Clone)]
 pub struct Incomplete {
     pub buffer: [u8; 4],
     pub buffer_len: u8,
 }
 
-pub fn decode(input: &[u8]) -> Result<&str, DecodeError> {
-    let error = match str::from_utf8(input) {
-        Ok(valid) => return Ok(valid),
-        Err(error) => error,
-    };
+/// The error returned by `Incomplete::try_complete_checked` for an `Incomplete`
+/// that does not hold the start of a code point.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IncompleteStateError {
+    /// The buffer is empty, for example because `try_complete` already returned `Some(_)`.
+    Empty,
+
+    /// `buffer_len` is out of range, or the buffered bytes are not
+    /// the incomplete start of one code point.
+    Malformed,
+}
+
+impl fmt::Display for IncompleteStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            IncompleteStateError::Empty => "no incomplete code point is buffered",
+            IncompleteStateError::Malformed => "the buffer does not hold an incomplete code point",
+        })
+    }
+}
 
-    // FIXME: separate function from here to guide inlining?
-    let (valid, after_valid) = input.split_at(error.valid_up_to());
+#[cfg(feature = "std")]
+impl std::error::Error for IncompleteStateError {}
+
+impl fmt::Debug for Incomplete {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.buffer.get(..self.buffer_len as usize) {
+            Some(bytes) => f.debug_tuple("Incomplete").field(&HexBytes(bytes)).finish(),
+            // Public fields set to something inconsistent: show them as they are.
+            None => f.debug_struct("Incomplete")
+                .field("buffer", &HexBytes(&self.buffer))
+                .field("buffer_len", &self.buffer_len)
+                .finish(),
+        }
+    }
+}
+
+/// Only the bytes buffered so far are compared, not the rest of `buffer`.
+impl PartialEq for Incomplete {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.buffer.get(..self.buffer_len as usize),
+               other.buffer.get(..other.buffer_len as usize)) {
+            (Some(bytes), Some(other_bytes)) => bytes == other_bytes,
+            // Public fields set to something inconsistent: compare them as they are.
+            _ => self.buffer == other.buffer && self.buffer_len == other.buffer_len,
+        }
+    }
+}
+
+impl Eq for Incomplete {}
+
+#[inline]
+#[must_use = "the error must be handled, or `decode_lossy` used to replace it"]
+pub fn decode(input: &[u8]) -> Result<&str, DecodeError<'_>> {
+    match utf8_error(input) {
+        None => Ok(unsafe { str::from_utf8_unchecked(input) }),
+        Some((valid_up_to, error_len)) => Err(decode_error(input, valid_up_to, error_len)),
+    }
+}
+
+/// The error path of `decode`, kept out of line so that the valid case is straight-line code.
+#[cold]
+fn decode_error(input: &[u8], valid_up_to: usize, error_len: Option<usize>) -> DecodeError<'_> {
+    let (valid, after_valid) = input.split_at(valid_up_to);
     let valid = unsafe {
         str::from_utf8_unchecked(valid)
     };
 
-    match error.error_len() {
+    match error_len {
         Some(invalid_sequence_length) => {
             let (invalid, rest) = after_valid.split_at(invalid_sequence_length);
-            Err(DecodeError::Invalid {
+            DecodeError::Invalid {
                 valid_prefix: valid,
                 invalid_sequence: invalid,
+                kind: Utf8ErrorKind::classify(after_valid),
                 remaining_input: rest
-            })
+            }
         }
         None => {
-            Err(DecodeError::Incomplete {
+            DecodeError::Incomplete {
                 valid_prefix: valid,
                 incomplete_suffix: Incomplete::new(after_valid),
+            }
+        }
+    }
+}
+
+/// Return the length of the longest prefix of `input` that is valid UTF-8,
+/// made of complete code points.
+///
+/// This is `str::from_utf8`’s `Utf8Error::valid_up_to`, or `input.len()` for valid input.
+/// A trailing sequence that is incomplete but valid so far is not included,
+/// so `&input[..valid_prefix_len(input)]` can be sliced off a buffer
+/// without splitting a code point.
+#[inline]
+pub fn valid_prefix_len(input: &[u8]) -> usize {
+    match utf8_error(input) {
+        None => input.len(),
+        Some((valid_up_to, _)) => valid_up_to,
+    }
+}
+
+/// Decode `input` for positions only, without building `&str` slices:
+/// `Ok(input.len())` if it is valid, otherwise `Err((valid_up_to, error_len))`
+/// with the same meaning as `DecodeError::as_std_like`.
+///
+/// ```rust
+/// assert_eq!(utf8::decode_positions(b"ab\xFFcd"), Err((2, Some(1))));
+/// assert_eq!(utf8::decode_positions(b"ab\xE2\x82"), Err((2, None)));
+/// assert_eq!(utf8::decode_positions(b"abc"), Ok(3));
+/// ```
+#[inline]
+pub fn decode_positions(input: &[u8]) -> Result<usize, (usize, Option<usize>)> {
+    match utf8_error(input) {
+        None => Ok(input.len()),
+        Some(error) => Err(error),
+    }
+}
+
+/// Decode the first code point of `input`,
+/// and return it with the number of bytes it occupies.
+///
+/// This is `str::chars().next()` for bytes not known to be valid UTF-8,
+/// for parsers that advance a cursor one code point at a time.
+///
+/// ```rust
+/// use utf8::{decode_one, DecodeOneError, Utf8ErrorKind};
+///
+/// assert_eq!(decode_one("é!".as_bytes()), Ok(('é', 2)));
+/// assert_eq!(decode_one(b"\xC3("),
+///            Err(DecodeOneError::Invalid { len: 1, kind: Utf8ErrorKind::InvalidContinuation }));
+/// assert_eq!(decode_one(b"\xE2\x82"), Err(DecodeOneError::Incomplete { len: 2 }));
+/// assert_eq!(decode_one(b""), Err(DecodeOneError::Empty));
+/// ```
+#[inline]
+pub fn decode_one(input: &[u8]) -> Result<(char, usize), DecodeOneError> {
+    let first = *input.first().ok_or(DecodeOneError::Empty)?;
+    if first.is_ascii() {
+        return Ok((first as char, 1))
+    }
+    // Bytes that can not start a sequence are checked as a sequence of one byte.
+    let len = cmp::min(cmp::max(utf8_char_width(first), 1), input.len());
+    match utf8_error(&input[..len]) {
+        None => {
+            let s = unsafe { str::from_utf8_unchecked(&input[..len]) };
+            Ok((s.chars().next().unwrap(), len))
+        }
+        Some((_, Some(error_len))) => {
+            Err(DecodeOneError::Invalid { len: error_len, kind: Utf8ErrorKind::classify(input) })
+        }
+        Some((_, None)) => Err(DecodeOneError::Incomplete { len }),
+    }
+}
+
+/// The error type of `decode_one`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DecodeOneError {
+    /// The input is empty.
+    Empty,
+
+    /// The input starts with an ill-formed sequence of `len` bytes.
+    /// Skip them, and in lossy decoding emit U+FFFD.
+    Invalid { len: usize, kind: Utf8ErrorKind },
+
+    /// The input is the first `len` bytes of a code point, too short to complete it.
+    Incomplete { len: usize },
+}
+
+impl fmt::Display for DecodeOneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeOneError::Empty => f.write_str("no code point in empty input"),
+            DecodeOneError::Invalid { len, kind } => {
+                write!(f, "found invalid byte sequence of {} bytes ({})", len, kind)
+            }
+            DecodeOneError::Incomplete { len } => {
+                write!(f, "found incomplete byte sequence of {} bytes", len)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeOneError {}
+
+/// Return whether `input` is entirely valid UTF-8.
+///
+/// This is `str::from_utf8(input).is_ok()`, for use in conditions.
+#[inline]
+pub fn is_valid_utf8(input: &[u8]) -> bool {
+    str::from_utf8(input).is_ok()
+}
+
+/// Split `input` into its longest valid prefix and the rest.
+///
+/// The rest is empty for valid input. Otherwise it starts either with an invalid sequence,
+/// or with an incomplete sequence of 1 to 3 bytes that makes up all of it.
+/// Use `decode` on the rest to find out which.
+#[inline]
+pub fn decode_prefix(input: &[u8]) -> (&str, &[u8]) {
+    let (valid, rest) = input.split_at(valid_prefix_len(input));
+    (unsafe { str::from_utf8_unchecked(valid) }, rest)
+}
+
+/// Like `decode`, for input that may or may not be followed by more input.
+///
+/// If `at_eof` is false, this is the same as `decode`:
+/// `DecodeError::Incomplete` means that more input is needed to decide.
+/// If `at_eof` is true, an incomplete sequence at the end is definitely invalid
+/// and is returned as `DecodeError::Invalid` with `Utf8ErrorKind::Truncated`,
+/// so that `DecodeError::Incomplete` is never returned.
+pub fn decode_frame(input: &[u8], at_eof: bool) -> Result<&str, DecodeError<'_>> {
+    match decode(input) {
+        Err(DecodeError::Incomplete { valid_prefix, .. }) if at_eof => {
+            Err(DecodeError::Invalid {
+                valid_prefix,
+                invalid_sequence: &input[valid_prefix.len()..],
+                kind: Utf8ErrorKind::Truncated,
+                remaining_input: &[],
             })
         }
+        result => result,
+    }
+}
+
+/// Find the first invalid sequence of `input`, and return `(valid_up_to, resume_from)`:
+/// the end of the valid prefix, and the offset after the invalid sequence
+/// where decoding can resume.
+///
+/// Return `None` if `input` is valid, or valid except for an incomplete sequence at the end.
+#[inline]
+pub fn first_error(input: &[u8]) -> Option<(usize, usize)> {
+    match decode(input) {
+        Err(DecodeError::Invalid { valid_prefix, invalid_sequence, .. }) => {
+            let valid_up_to = valid_prefix.len();
+            Some((valid_up_to, valid_up_to + invalid_sequence.len()))
+        }
+        Ok(_) | Err(DecodeError::Incomplete { .. }) => None,
+    }
+}
+
+/// Return how many bytes the UTF-8 sequence for one code point occupies,
+/// given its first byte.
+///
+/// Return 0 for bytes that can not start a sequence:
+/// continuation bytes, and bytes that never occur in UTF-8 (0xC0, 0xC1, and 0xF5 to 0xFF).
+#[inline]
+pub const fn utf8_char_width(first_byte: u8) -> usize {
+    match first_byte {
+        0x00..=0x7F => 1,
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => 0,
+    }
+}
+
+/// Return whether `byte` is a continuation byte, 0x80 to 0xBF,
+/// which can only occur after the first byte of a multi-byte sequence.
+#[inline]
+pub const fn is_continuation_byte(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+/// Where `input` stops being valid UTF-8: `None` if it is valid, otherwise
+/// `Some((valid_up_to, error_len))` as in `str::Utf8Error`.
+///
+/// Every decoding path computes where to resume after an error through this function,
+/// so that they all agree with `str::from_utf8`.
+#[inline]
+pub(crate) fn utf8_error(input: &[u8]) -> Option<(usize, Option<usize>)> {
+    match str::from_utf8(input) {
+        Ok(_) => None,
+        Err(error) => Some((error.valid_up_to(), error.error_len())),
+    }
+}
+
+/// Decode `input` lossily, replacing errors with U+FFFD.
+///
+/// Like `String::from_utf8_lossy`, this returns `Cow::Borrowed` without allocating
+/// when `input` is entirely valid UTF-8, and `Cow::Owned` otherwise.
+#[cfg(feature = "alloc")]
+pub fn decode_lossy(input: &[u8]) -> Cow<'_, str> {
+    let result = decode(input);
+    if let Ok(s) = result {
+        return Cow::Borrowed(s)
+    }
+    let mut string = String::with_capacity(input.len() + REPLACEMENT_CHARACTER.len());
+    push_lossy(result, |s| string.push_str(s));
+    Cow::Owned(string)
+}
+
+/// Decode `input` lossily, replacing errors with U+FFFD, and append the result to `out`.
+///
+/// `out` is appended to, not cleared first.
+/// Together with `String::clear`, this allows reusing one buffer for many inputs.
+#[cfg(feature = "alloc")]
+pub fn decode_lossy_into(input: &[u8], out: &mut String) {
+    push_lossy(decode(input), |s| out.push_str(s))
+}
+
+/// Decode `input` lossily, replacing errors with U+FFFD, into as much of `out` as fits.
+///
+/// Return `(input_consumed, output_written)`. This stops at the end of `input`,
+/// or before the first code point (or 3-byte U+FFFD) that does not fit in `out`,
+/// so that output is always valid UTF-8. Call it again with `&input[input_consumed..]`
+/// once `out` is drained. With `out` of 4 bytes or more, some progress is always made.
+///
+/// `input` is taken to be the rest of the stream:
+/// an incomplete sequence at its end is an error.
+/// This does not allocate, so it also works without the `alloc` feature.
+pub fn decode_lossy_to_slice(input: &[u8], out: &mut [u8]) -> (usize, usize) {
+    let mut consumed = 0;
+    let mut written = 0;
+    while consumed < input.len() {
+        let (valid, after_valid) = decode_prefix(&input[consumed..]);
+        let space = out.len() - written;
+        if valid.len() > space {
+            let mut len = space;
+            while !valid.is_char_boundary(len) {
+                len -= 1
+            }
+            out[written..written + len].copy_from_slice(&valid.as_bytes()[..len]);
+            return (consumed + len, written + len)
+        }
+        out[written..written + valid.len()].copy_from_slice(valid.as_bytes());
+        consumed += valid.len();
+        written += valid.len();
+        if after_valid.is_empty() {
+            break
+        }
+        let invalid_sequence_len = match utf8_error(after_valid) {
+            Some((_, Some(len))) => len,
+            // Incomplete at the end of the input.
+            _ => after_valid.len(),
+        };
+        if out.len() - written < REPLACEMENT_CHARACTER.len() {
+            break
+        }
+        out[written..written + REPLACEMENT_CHARACTER.len()]
+            .copy_from_slice(REPLACEMENT_CHARACTER.as_bytes());
+        consumed += invalid_sequence_len;
+        written += REPLACEMENT_CHARACTER.len();
+    }
+    (consumed, written)
+}
+
+/// Decode `input` lossily, replacing errors with U+FFFD, and write the result to `out`.
+///
+/// This does not allocate, so it also works without the `alloc` feature.
+/// For example, to display bytes:
+///
+/// ```rust
+/// use std::fmt;
+///
+/// struct MyBytes(Vec<u8>);
+///
+/// impl fmt::Display for MyBytes {
+///     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+///         utf8::write_lossy(f, &self.0)
+///     }
+/// }
+///
+/// assert_eq!(MyBytes(b"caf\xC3\xA9 \xFF".to_vec()).to_string(), "café \u{FFFD}");
+/// ```
+pub fn write_lossy(out: &mut dyn fmt::Write, input: &[u8]) -> fmt::Result {
+    let mut result = Ok(());
+    push_lossy(decode(input), |s| {
+        if result.is_ok() {
+            result = out.write_str(s)
+        }
+    });
+    result
+}
+
+/// Decode `input` lossily, replacing errors with U+FFFD, in windows of about `chunk` bytes,
+/// and call `on_progress` with the number of bytes decoded so far after each window.
+///
+/// Windows are extended past `chunk` bytes to the next code point boundary,
+/// so the output is the same as decoding `input` at once.
+/// This suits large inputs such as memory-mapped files, with progress reporting.
+///
+/// ```rust
+/// let mut string = String::new();
+/// let mut progress = Vec::new();
+/// utf8::decode_lossy_with_progress("aé€\u{1F4A9}".as_bytes(), 2,
+///                                  |s| string.push_str(s), |done| progress.push(done));
+/// assert_eq!(string, "aé€\u{1F4A9}");
+/// assert_eq!(progress, [3, 6, 10]);
+/// ```
+///
+/// Panics if `chunk` is zero.
+pub fn decode_lossy_with_progress<F, P>(input: &[u8], chunk: usize, mut on_str: F,
+                                        mut on_progress: P)
+    where F: FnMut(&str), P: FnMut(usize)
+{
+    assert!(chunk > 0, "chunk size must be non-zero");
+    let mut start = 0;
+    while start < input.len() {
+        let mut end = start.saturating_add(chunk).min(input.len());
+        // Past three continuation bytes, any code point before them is complete or invalid.
+        let max_end = end.saturating_add(3).min(input.len());
+        while end < max_end && is_continuation_byte(input[end]) {
+            end += 1
+        }
+        push_lossy(decode(&input[start..end]), |s| {
+            if !s.is_empty() {
+                on_str(s)
+            }
+        });
+        on_progress(end);
+        start = end;
+    }
+}
+
+/// Same as `decode_lossy_into`, but append the UTF-8 bytes of the result to a `Vec<u8>`.
+///
+/// Only valid UTF-8 is appended:
+/// if `out` was valid UTF-8 before the call, it still is after.
+#[cfg(feature = "alloc")]
+pub fn decode_lossy_into_vec(input: &[u8], out: &mut Vec<u8>) {
+    push_lossy(decode(input), |s| out.extend_from_slice(s.as_bytes()))
+}
+
+/// Decode `input` lossily, replacing errors with U+FFFD, into an owned `String`.
+///
+/// When `input` is entirely valid UTF-8, its allocation is reused without copying.
+/// Otherwise the result is built in a new `String` with a capacity of `input.len()`,
+/// which only grows if replacements make the output longer than the input.
+#[cfg(feature = "alloc")]
+pub fn into_valid_utf8(input: Vec<u8>) -> String {
+    match String::from_utf8(input) {
+        Ok(string) => string,
+        Err(error) => {
+            let input = error.as_bytes();
+            let mut string = String::with_capacity(input.len());
+            decode_lossy_into(input, &mut string);
+            string
+        }
+    }
+}
+
+/// Return `input` with every non-ASCII scalar value escaped as `\u{XXXX}`, in lowercase hex.
+///
+/// ASCII characters pass through unchanged, including `\`,
+/// so the result is not unambiguous unless the input has no backslash.
+#[cfg(feature = "alloc")]
+pub fn to_ascii_escaped(input: &str) -> String {
+    let mut string = String::with_capacity(input.len());
+    for c in input.chars() {
+        if c.is_ascii() {
+            string.push(c)
+        } else {
+            string.extend(c.escape_unicode())
+        }
+    }
+    string
+}
+
+/// Decode `input` lossily like `decode_lossy`, then escape it like `to_ascii_escaped`.
+#[cfg(feature = "alloc")]
+pub fn to_ascii_escaped_lossy(input: &[u8]) -> String {
+    to_ascii_escaped(&decode_lossy(input))
+}
+
+/// One error found by `decode_collect_errors`.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorRecord {
+    /// The position of `bytes` in the input.
+    pub byte_offset: usize,
+    pub bytes: Vec<u8>,
+    pub kind: Utf8ErrorKind,
+}
+
+/// Decode `input` lossily, replacing errors with U+FFFD,
+/// and also return a record of each error in the same pass.
+#[cfg(feature = "alloc")]
+pub fn decode_collect_errors(input: &[u8]) -> (String, Vec<ErrorRecord>) {
+    let mut string = String::with_capacity(input.len());
+    let mut errors = Vec::new();
+    let mut remaining = input;
+    loop {
+        match decode(remaining) {
+            Ok(s) => {
+                string.push_str(s);
+                return (string, errors)
+            }
+            Err(DecodeError::Incomplete { valid_prefix, incomplete_suffix }) => {
+                string.push_str(valid_prefix);
+                string.push_str(REPLACEMENT_CHARACTER);
+                errors.push(ErrorRecord {
+                    byte_offset: input.len() - incomplete_suffix.buffer_len as usize,
+                    bytes: incomplete_suffix.buffered_bytes().to_vec(),
+                    kind: Utf8ErrorKind::Truncated,
+                });
+                return (string, errors)
+            }
+            Err(DecodeError::Invalid { valid_prefix, invalid_sequence, kind, remaining_input }) => {
+                string.push_str(valid_prefix);
+                string.push_str(REPLACEMENT_CHARACTER);
+                errors.push(ErrorRecord {
+                    byte_offset: input.len() - remaining_input.len() - invalid_sequence.len(),
+                    bytes: invalid_sequence.to_vec(),
+                    kind,
+                });
+                remaining = remaining_input;
+            }
+        }
+    }
+}
+
+fn push_lossy<'a, F: FnMut(&str)>(mut result: Result<&'a str, DecodeError<'a>>, mut push_str: F) {
+    loop {
+        match result {
+            Ok(s) => {
+                push_str(s);
+                return
+            }
+            Err(DecodeError::Incomplete { valid_prefix, .. }) => {
+                push_str(valid_prefix);
+                push_str(REPLACEMENT_CHARACTER);
+                return
+            }
+            Err(DecodeError::Invalid { valid_prefix, remaining_input, .. }) => {
+                push_str(valid_prefix);
+                push_str(REPLACEMENT_CHARACTER);
+                result = decode(remaining_input);
+            }
+        }
     }
 }
 
 impl Incomplete {
+    /// An empty buffer, for when the previous chunk did not end in the middle of a code point.
     pub fn empty() -> Self {
         Incomplete {
             buffer: [0, 0, 0, 0],
@@ -110,20 +1064,69 @@ impl Incomplete {
         self.buffer_len == 0
     }
 
+    /// The bytes buffered so far, `buffer[..buffer_len]`.
+    pub fn buffered_bytes(&self) -> &[u8] {
+        &self.buffer[..self.buffer_len as usize]
+    }
+
+    /// How many bytes the code point being buffered occupies in total,
+    /// based on its first byte. Zero if the buffer is empty.
+    pub fn char_width(&self) -> usize {
+        if self.is_empty() {
+            0
+        } else {
+            utf8_char_width(self.buffer[0])
+        }
+    }
+
+    /// How many more bytes are needed to complete the code point being buffered.
+    ///
+    /// If fewer bytes are available, `try_complete` is certain to return `None`.
+    pub fn bytes_needed(&self) -> usize {
+        self.char_width().saturating_sub(self.buffer_len as usize)
+    }
+
+    /// Start buffering `bytes`, which should be the incomplete start of one code point
+    /// such as the `incomplete_suffix` of `DecodeError::Incomplete`.
+    ///
+    /// Panics if `bytes` is longer than 4 bytes.
     pub fn new(bytes: &[u8]) -> Self {
         let mut buffer = [0, 0, 0, 0];
         let len = bytes.len();
         buffer[..len].copy_from_slice(bytes);
         Incomplete {
-            buffer: buffer,
+            buffer,
             buffer_len: len as u8,
         }
     }
 
+    /// Signal that no more input is available.
+    /// Buffered bytes are then a code point cut off by the end of the input,
+    /// returned as an `InvalidSequenceBuf` with `incomplete` set.
+    ///
+    /// ```rust
+    /// let error = utf8::decode(b"ab\xE2\x82").unwrap_err();
+    /// if let utf8::DecodeError::Incomplete { incomplete_suffix, .. } = error {
+    ///     assert_eq!(incomplete_suffix.finalize().unwrap_err().bytes, b"\xE2\x82");
+    /// }
+    /// assert_eq!(utf8::Incomplete::empty().finalize(), Ok(()));
+    /// ```
+    #[cfg(feature = "alloc")]
+    pub fn finalize(self) -> Result<(), InvalidSequenceBuf> {
+        if self.is_empty() {
+            return Ok(())
+        }
+        Err(InvalidSequenceBuf {
+            bytes: self.buffered_bytes().into(),
+            incomplete: true,
+        })
+    }
+
     /// * `None`: still incomplete, call `try_complete` again with more input.
     ///   If no more input is available, this is invalid byte sequence.
     /// * `Some((result, remaining_input))`: We’re done with this `Incomplete`.
     ///   To keep decoding, pass `remaining_input` to `decode()`.
+    #[allow(clippy::type_complexity)]
     pub fn try_complete<'input>(&mut self, input: &'input [u8])
                                 -> Option<(Result<&str, &[u8]>, &'input [u8])> {
         let (consumed, opt_result) = self.try_complete_offsets(input);
@@ -137,16 +1140,45 @@ impl Incomplete {
         Some((result, remaining_input))
     }
 
-    fn take_buffer(&mut self) -> &[u8] {
+    /// Like `try_complete`, but check first that this `Incomplete` holds
+    /// the start of a code point, and return an error instead of misbehaving or panicking
+    /// if it does not. This is the case after `try_complete` returned `Some(_)`,
+    /// or if the public fields were set to something inconsistent.
+    #[allow(clippy::type_complexity)]
+    pub fn try_complete_checked<'input>(&mut self, input: &'input [u8])
+        -> Result<Option<(Result<&str, &[u8]>, &'input [u8])>, IncompleteStateError> {
+        if self.buffer_len == 0 {
+            return Err(IncompleteStateError::Empty)
+        }
+        if self.buffer_len > 3 {
+            return Err(IncompleteStateError::Malformed)
+        }
+        match decode(self.buffered_bytes()) {
+            Err(DecodeError::Incomplete { valid_prefix: "", .. }) => {}
+            _ => return Err(IncompleteStateError::Malformed),
+        }
+        Ok(self.try_complete(input))
+    }
+
+    /// Return the bytes buffered so far and empty the buffer.
+    ///
+    /// After `try_complete_offsets` returns `Some(_)`,
+    /// these are the bytes of the completed code point, or of the invalid sequence.
+    pub fn take_buffer(&mut self) -> &[u8] {
         let len = self.buffer_len as usize;
         self.buffer_len = 0;
-        &self.buffer[..len as usize]
+        &self.buffer[..len]
     }
 
-    /// (consumed_from_input, None): not enough input
-    /// (consumed_from_input, Some(Err(()))): error bytes in buffer
-    /// (consumed_from_input, Some(Ok(()))): UTF-8 string in buffer
-    fn try_complete_offsets(&mut self, input: &[u8]) -> (usize, Option<Result<(), ()>>) {
+    /// Like `try_complete`, but return how many bytes of `input` were used
+    /// and leave the result in the buffer, to be taken with `take_buffer`:
+    ///
+    /// * `(consumed_from_input, None)`: not enough input, all of it was buffered.
+    /// * `(consumed_from_input, Some(Err(())))`: error bytes in buffer
+    /// * `(consumed_from_input, Some(Ok(())))`: UTF-8 string in buffer.
+    ///   This may include more complete code points after the one that was completed,
+    ///   up to the 4 bytes of the buffer.
+    pub fn try_complete_offsets(&mut self, input: &[u8]) -> (usize, Option<Result<(), ()>>) {
         let initial_buffer_len = self.buffer_len as usize;
         let copied_from_input;
         {
@@ -155,19 +1187,18 @@ impl Incomplete {
             unwritten[..copied_from_input].copy_from_slice(&input[..copied_from_input]);
         }
         let spliced = &self.buffer[..initial_buffer_len + copied_from_input];
-        match str::from_utf8(spliced) {
-            Ok(_) => {
+        match utf8_error(spliced) {
+            None => {
                 self.buffer_len = spliced.len() as u8;
                 (copied_from_input, Some(Ok(())))
             }
-            Err(error) => {
-                let valid_up_to = error.valid_up_to();
+            Some((valid_up_to, error_len)) => {
                 if valid_up_to > 0 {
                     let consumed = valid_up_to.checked_sub(initial_buffer_len).unwrap();
                     self.buffer_len = valid_up_to as u8;
                     (consumed, Some(Ok(())))
                 } else {
-                    match error.error_len() {
+                    match error_len {
                         Some(invalid_sequence_length) => {
                             let consumed = invalid_sequence_length
                                 .checked_sub(initial_buffer_len).unwrap();
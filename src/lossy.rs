@@ -1,4 +1,5 @@
 use super::*;
+use core::marker::PhantomData;
 
 /// A push-based, lossy decoder for UTF-8.
 /// Errors are replaced with the U+FFFD replacement character.
@@ -20,24 +21,285 @@ use super::*;
 /// If the last input chunk ended with an incomplete byte sequence for a code point,
 /// this is an error and a replacement character is emitted.
 /// Use `std::mem::forget` to inhibit this behavior.
-pub struct LossyDecoder<F: FnMut(&str)> {
+///
+/// The decoder is `Clone` when the callbacks are,
+/// which allows taking a snapshot of its state in the middle of a stream.
+#[derive(Clone)]
+pub struct LossyDecoder<F: FnMut(&str), E: FnMut(&[u8]) = fn(&[u8]), R: Replacement = &'static str> {
     push_str: F,
+    on_error: E,
     incomplete: Incomplete,
+    policy: ReplacementPolicy,
+    replacement: R,
+    truncate_incomplete_silently: bool,
+    newlines: Newlines,
+    limit: Limit,
+}
+
+/// How much more output a `LossyDecoder` can push, for `with_limit`.
+#[derive(Clone)]
+struct Limit {
+    remaining_bytes: usize,
+    truncated: bool,
+}
+
+impl Limit {
+    /// Push as much of `s` as fits, up to a code point boundary.
+    /// Return whether `push_str` was called.
+    #[inline]
+    fn push<F: FnMut(&str)>(&mut self, s: &str, push_str: &mut F) -> bool {
+        if s.len() <= self.remaining_bytes {
+            self.remaining_bytes -= s.len();
+            push_str(s);
+            return true
+        }
+        let mut len = self.remaining_bytes;
+        while !s.is_char_boundary(len) {
+            len -= 1
+        }
+        if len > 0 {
+            push_str(&s[..len])
+        }
+        self.remaining_bytes = 0;
+        self.truncated = true;
+        len > 0
+    }
+}
+
+/// Line ending conversion in a `LossyDecoder`, for `normalize_newlines`.
+#[derive(Clone)]
+struct Newlines {
+    normalize: bool,
+    /// The last output was a `\r` converted to `\n`, so drop a `\n` that comes next.
+    after_cr: bool,
+}
+
+impl Newlines {
+    /// Push `s` with `\r\n` and `\r` converted to `\n`, if enabled.
+    /// Return whether `push_str` was called.
+    #[inline]
+    fn push<F: FnMut(&str) -> bool>(&mut self, mut s: &str, push_str: &mut F) -> bool {
+        if !self.normalize {
+            return push_str(s)
+        }
+        let mut pushed = false;
+        while !s.is_empty() {
+            if self.after_cr && s.as_bytes()[0] == b'\n' {
+                s = &s[1..];
+            }
+            self.after_cr = false;
+            match s.as_bytes().iter().position(|&byte| byte == b'\r') {
+                None => {
+                    if !s.is_empty() {
+                        pushed |= push_str(s)
+                    }
+                    break
+                }
+                Some(position) => {
+                    if position > 0 {
+                        pushed |= push_str(&s[..position])
+                    }
+                    pushed |= push_str("\n");
+                    self.after_cr = true;
+                    s = &s[position + 1..];
+                }
+            }
+        }
+        pushed
+    }
+}
+
+/// How many replacement characters to emit for an ill-formed byte sequence.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ReplacementPolicy {
+    /// One U+FFFD per maximal subpart of an ill-formed sequence,
+    /// as recommended by Unicode and required by the WHATWG Encoding Standard.
+    /// This is the same as `String::from_utf8_lossy`, and the default.
+    #[default]
+    MaximalSubpart,
+
+    /// One U+FFFD per byte of an ill-formed sequence.
+    PerByte,
+
+    /// Drop ill-formed sequences without emitting anything in their place.
+    Skip,
+}
+
+/// How a `LossyDecoder` chooses the string that replaces each error.
+///
+/// Any `AsRef<str>` type, such as `&str`, is one string for all errors.
+/// `KindReplacement` chooses a string for each kind of error.
+pub trait Replacement {
+    /// The string that replaces an error of this kind.
+    fn for_error(&mut self, kind: Utf8ErrorKind) -> &str;
+}
+
+impl<T: AsRef<str>> Replacement for T {
+    #[inline]
+    fn for_error(&mut self, _kind: Utf8ErrorKind) -> &str {
+        (*self).as_ref()
+    }
+}
+
+/// A `Replacement` that calls a function to choose the string for each kind of error.
+/// See `LossyDecoder::with_kind_replacement`.
+#[derive(Clone)]
+pub struct KindReplacement<'a, G> {
+    kind_replacement: G,
+    // The returned strings can be borrowed for as long as `self` is.
+    strings: PhantomData<&'a str>,
+}
+
+impl<'a, G: FnMut(Utf8ErrorKind) -> &'a str> Replacement for KindReplacement<'a, G> {
+    #[inline]
+    fn for_error(&mut self, kind: Utf8ErrorKind) -> &str {
+        (self.kind_replacement)(kind)
+    }
+}
+
+impl ReplacementPolicy {
+    fn push_replacement<F: FnMut(&str)>(self, invalid_sequence: &[u8], replacement: &str,
+                                        push_str: &mut F) {
+        if replacement.is_empty() {
+            return
+        }
+        match self {
+            ReplacementPolicy::MaximalSubpart => push_str(replacement),
+            ReplacementPolicy::PerByte => {
+                for _ in invalid_sequence {
+                    push_str(replacement)
+                }
+            }
+            ReplacementPolicy::Skip => {}
+        }
+    }
 }
 
 impl<F: FnMut(&str)> LossyDecoder<F> {
     /// Create a new decoder from a callback.
     #[inline]
     pub fn new(push_str: F) -> Self {
+        Self::with_policy(push_str, ReplacementPolicy::MaximalSubpart)
+    }
+
+    /// Create a new decoder from a callback, with the given replacement policy.
+    #[inline]
+    pub fn with_policy(push_str: F, policy: ReplacementPolicy) -> Self {
+        LossyDecoder::from_parts(push_str, ignore_error, policy, REPLACEMENT_CHARACTER)
+    }
+
+    /// Create a new decoder from a callback, which pushes at most `max_bytes` of output.
+    ///
+    /// Output stops at the last code point boundary within the limit,
+    /// and the rest of the input is ignored. `truncated` tells whether this happened.
+    /// Note that `feed` still decodes the rest of a chunk in which the limit is reached.
+    #[inline]
+    pub fn with_limit(push_str: F, max_bytes: usize) -> Self {
+        let mut decoder = Self::new(push_str);
+        decoder.limit.remaining_bytes = max_bytes;
+        decoder
+    }
+}
+
+impl<F: FnMut(&str), R: AsRef<str>> LossyDecoder<F, fn(&[u8]), R> {
+    /// Create a new decoder from a callback,
+    /// which replaces errors with `replacement` instead of U+FFFD.
+    ///
+    /// `replacement` can be borrowed, for example from a `String` in runtime configuration.
+    /// An empty replacement drops errors, like `ReplacementPolicy::Skip`.
+    #[inline]
+    pub fn with_replacement(push_str: F, replacement: R) -> Self {
+        LossyDecoder::from_parts(push_str, ignore_error, ReplacementPolicy::MaximalSubpart,
+                                 replacement)
+    }
+}
+
+fn ignore_error(_invalid_sequence: &[u8]) {}
+
+impl<'a, F: FnMut(&str), G: FnMut(Utf8ErrorKind) -> &'a str>
+    LossyDecoder<F, fn(&[u8]), KindReplacement<'a, G>> {
+    /// Create a new decoder from a callback, which replaces each error
+    /// with the string returned by `kind_replacement` for its kind,
+    /// for example to make different kinds of corruption visible in the output.
+    ///
+    /// An incomplete sequence at the end of the input is `Utf8ErrorKind::Truncated`.
+    /// Empty strings drop errors, as with `with_replacement`.
+    #[inline]
+    pub fn with_kind_replacement(push_str: F, kind_replacement: G) -> Self {
+        LossyDecoder::from_parts(push_str, ignore_error, ReplacementPolicy::MaximalSubpart,
+                                 KindReplacement { kind_replacement, strings: PhantomData })
+    }
+}
+
+impl<F: FnMut(&str), E: FnMut(&[u8])> LossyDecoder<F, E> {
+    /// Create a new decoder from a callback for decoded output,
+    /// and one for the bytes of each ill-formed sequence.
+    ///
+    /// `on_error` is called once per replacement character,
+    /// with the maximal subpart of an ill-formed sequence it replaces,
+    /// just before that replacement is pushed.
+    /// This includes the incomplete sequence at the end, on drop.
+    /// The output of `push_str` is the same as with `new`.
+    #[inline]
+    pub fn with_error_callback(push_str: F, on_error: E) -> Self {
+        LossyDecoder::from_parts(push_str, on_error, ReplacementPolicy::MaximalSubpart,
+                                 REPLACEMENT_CHARACTER)
+    }
+}
+
+impl<F: FnMut(&str), E: FnMut(&[u8]), R: Replacement> LossyDecoder<F, E, R> {
+    #[inline]
+    fn from_parts(push_str: F, on_error: E, policy: ReplacementPolicy, replacement: R) -> Self {
         LossyDecoder {
-            push_str: push_str,
+            push_str,
+            on_error,
             incomplete: Incomplete {
                 buffer: [0, 0, 0, 0],
                 buffer_len: 0,
             },
+            policy,
+            replacement,
+            truncate_incomplete_silently: false,
+            newlines: Newlines { normalize: false, after_cr: false },
+            limit: Limit { remaining_bytes: usize::MAX, truncated: false },
         }
     }
 
+    /// Whether the decoder holds the start of a code point from previous input,
+    /// waiting for more input to complete it.
+    /// If so, dropping the decoder emits a replacement character,
+    /// unless `truncate_incomplete_silently` is set.
+    #[inline]
+    pub fn has_incomplete(&self) -> bool {
+        !self.incomplete.is_empty()
+    }
+
+    /// Whether output was cut off by the limit of `with_limit`,
+    /// so that some of the input was not pushed.
+    pub fn truncated(&self) -> bool {
+        self.limit.truncated
+    }
+
+    /// Whether to drop an incomplete sequence at the end of the input silently,
+    /// as for input cut off in the middle of a code point,
+    /// instead of emitting a replacement character for it on drop.
+    ///
+    /// This is disabled by default. Invalid sequences are always replaced,
+    /// since they are found by `feed` and never left for the end.
+    pub fn truncate_incomplete_silently(mut self, truncate: bool) -> Self {
+        self.truncate_incomplete_silently = truncate;
+        self
+    }
+
+    /// Whether to convert line endings `\r\n` and lone `\r` to `\n` in the output.
+    ///
+    /// A `\r` is pushed as `\n` right away, and a `\n` that follows it is dropped,
+    /// even when it only arrives in the next chunk. This is disabled by default.
+    pub fn normalize_newlines(mut self, normalize: bool) -> Self {
+        self.newlines.normalize = normalize;
+        self
+    }
+
     /// Feed one chunk of input into the decoder.
     ///
     /// The input is decoded lossily
@@ -45,48 +307,327 @@ impl<F: FnMut(&str)> LossyDecoder<F> {
     ///
     /// If the UTF-8 byte sequence for one code point was split into this bytes chunk
     /// and previous bytes chunks, it will be correctly pieced back together.
-    pub fn feed(&mut self, mut input: &[u8]) {
+    ///
+    /// Return whether the callback was called. This is `false` for example
+    /// when all of `input` is part of a code point that is still incomplete.
+    pub fn feed(&mut self, input: &[u8]) -> bool {
+        if self.limit.truncated {
+            return false
+        }
+        let newlines = &mut self.newlines;
+        let limit = &mut self.limit;
+        let push_str = &mut self.push_str;
+        let mut pushed = false;
+        feed_lossy_reporting(&mut self.incomplete, input, self.policy, &mut self.replacement,
+                             &mut |s: &str| {
+                                 pushed |= newlines.push(s, &mut |s| limit.push(s, push_str))
+                             },
+                             &mut self.on_error);
+        pushed
+    }
+
+    /// Feed each chunk of `chunks` into the decoder, in order.
+    ///
+    /// This is the same as calling `feed` for each chunk:
+    /// code points split between chunks are pieced back together.
+    pub fn feed_all<I: IntoIterator<Item = B>, B: AsRef<[u8]>>(&mut self, chunks: I) {
+        for chunk in chunks {
+            self.feed(chunk.as_ref());
+        }
+    }
+
+    /// Feed all remaining bytes of `buf` into the decoder, advancing it to its end.
+    ///
+    /// Each contiguous chunk of `buf` is fed in turn without copying,
+    /// and code points split between chunks are pieced back together.
+    #[cfg(feature = "bytes")]
+    pub fn feed_buf<B: bytes::Buf>(&mut self, buf: &mut B) {
+        while buf.has_remaining() {
+            let chunk = buf.chunk();
+            let len = chunk.len();
+            self.feed(chunk);
+            buf.advance(len)
+        }
+    }
+
+    /// Feed a chunk of input already known to be valid UTF-8, without decoding it again.
+    ///
+    /// A `&str` can not complete a code point left incomplete by previous chunks,
+    /// so any such incomplete sequence is an error and replaced first.
+    pub fn feed_str(&mut self, s: &str) {
+        if self.limit.truncated {
+            return
+        }
+        let newlines = &mut self.newlines;
+        let limit = &mut self.limit;
+        let push_str = &mut self.push_str;
+        let mut push = |s: &str| { newlines.push(s, &mut |s| limit.push(s, push_str)); };
         if self.incomplete.buffer_len > 0 {
-            match self.incomplete.try_complete(input) {
-                Some((Ok(s), remaining)) => {
-                    (self.push_str)(s);
-                    input = remaining
-                }
-                Some((Err(_), remaining)) => {
-                    (self.push_str)(REPLACEMENT_CHARACTER);
-                    input = remaining
-                }
-                None => {
-                    return
+            let invalid_sequence = self.incomplete.take_buffer();
+            (self.on_error)(invalid_sequence);
+            self.policy.push_replacement(invalid_sequence,
+                                         self.replacement.for_error(Utf8ErrorKind::Truncated),
+                                         &mut push)
+        }
+        if !s.is_empty() {
+            push(s);
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl LossyDecoder<fn(&str)> {
+    /// Create a new decoder that appends to `buf`, borrowing it for the life of the decoder.
+    ///
+    /// This is the same as `LossyDecoder::new(|s| buf.push_str(s))`.
+    /// Dropping the decoder appends the final replacement character to `buf`, if any.
+    #[inline]
+    pub fn to_buf(buf: &mut String) -> LossyDecoder<impl FnMut(&str) + '_> {
+        LossyDecoder::new(move |s| buf.push_str(s))
+    }
+}
+
+/// The shared implementation of lossy push-based decoders.
+///
+/// `push_str` is never called with an empty string.
+pub(crate) fn feed_lossy<F: FnMut(&str)>(incomplete: &mut Incomplete, input: &[u8],
+                                         policy: ReplacementPolicy, push_str: &mut F) {
+    feed_lossy_reporting(incomplete, input, policy, &mut { REPLACEMENT_CHARACTER }, push_str,
+                         &mut ignore_error)
+}
+
+/// Same as `feed_lossy`, but also call `on_error` with each ill-formed sequence.
+fn feed_lossy_reporting<F, E, R>(incomplete: &mut Incomplete, mut input: &[u8],
+                                 policy: ReplacementPolicy, replacement: &mut R,
+                                 push_str: &mut F, on_error: &mut E)
+    where F: FnMut(&str), E: FnMut(&[u8]), R: Replacement
+{
+    if incomplete.buffer_len > 0 {
+        // Past the second byte, any continuation byte is valid.
+        if let [byte @ 0x80..=0xBF] = *input {
+            if incomplete.buffer_len >= 2 {
+                incomplete.buffer[incomplete.buffer_len as usize] = byte;
+                incomplete.buffer_len += 1;
+                if incomplete.bytes_needed() == 0 {
+                    push_str(unsafe { str::from_utf8_unchecked(incomplete.take_buffer()) })
                 }
+                return
+            }
+        }
+        match incomplete.try_complete(input) {
+            Some((Ok(s), remaining)) => {
+                push_str(s);
+                input = remaining
             }
+            Some((Err(invalid_sequence), remaining)) => {
+                on_error(invalid_sequence);
+                let kind = classify_stitched(invalid_sequence, remaining);
+                policy.push_replacement(invalid_sequence, replacement.for_error(kind), push_str);
+                input = remaining
+            }
+            None => {
+                return
+            }
+        }
+    }
+    // Fast paths for tiny chunks, as from byte-at-a-time sources.
+    match *input {
+        [] => return,
+        [byte] if byte.is_ascii() => {
+            push_str(unsafe { str::from_utf8_unchecked(input) });
+            return
+        }
+        [byte] if utf8_char_width(byte) > 1 => {
+            *incomplete = Incomplete::new(input);
+            return
         }
-        loop {
-            match decode(input) {
-                Ok(s) => {
-                    (self.push_str)(s);
-                    return
+        _ => {}
+    }
+    loop {
+        match decode(input) {
+            Ok(s) => {
+                if !s.is_empty() {
+                    push_str(s)
                 }
-                Err(DecodeError::Incomplete { valid_prefix, incomplete_suffix }) => {
-                    (self.push_str)(valid_prefix);
-                    self.incomplete = incomplete_suffix;
-                    return
+                return
+            }
+            Err(DecodeError::Incomplete { valid_prefix, incomplete_suffix }) => {
+                if !valid_prefix.is_empty() {
+                    push_str(valid_prefix)
                 }
-                Err(DecodeError::Invalid { valid_prefix, remaining_input, .. }) => {
-                    (self.push_str)(valid_prefix);
-                    (self.push_str)(REPLACEMENT_CHARACTER);
-                    input = remaining_input
+                *incomplete = incomplete_suffix;
+                return
+            }
+            Err(DecodeError::Invalid { valid_prefix, invalid_sequence, kind, remaining_input }) => {
+                if !valid_prefix.is_empty() {
+                    push_str(valid_prefix)
                 }
+                on_error(invalid_sequence);
+                policy.push_replacement(invalid_sequence, replacement.for_error(kind), push_str);
+                input = remaining_input
             }
         }
     }
 }
 
-impl<F: FnMut(&str)> Drop for LossyDecoder<F> {
+impl<F: FnMut(&str), E: FnMut(&[u8]), R: Replacement> Drop for LossyDecoder<F, E, R> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.incomplete.buffer_len > 0 && !self.truncate_incomplete_silently
+                && !self.limit.truncated {
+            let newlines = &mut self.newlines;
+            let limit = &mut self.limit;
+            let push_str = &mut self.push_str;
+            (self.on_error)(self.incomplete.buffered_bytes());
+            self.policy.push_replacement(self.incomplete.buffered_bytes(),
+                                         self.replacement.for_error(Utf8ErrorKind::Truncated),
+                                         &mut |s: &str| {
+                                             newlines.push(s, &mut |s| limit.push(s, push_str));
+                                         })
+        }
+    }
+}
+
+/// The kind of an error found when completing a buffered sequence with `remaining_input`,
+/// which starts with the byte that ended the invalid sequence.
+fn classify_stitched(invalid_sequence: &[u8], remaining_input: &[u8]) -> Utf8ErrorKind {
+    // `classify` only looks at the first two bytes.
+    match (invalid_sequence, remaining_input.first()) {
+        (&[first], Some(&second)) => Utf8ErrorKind::classify(&[first, second]),
+        _ => Utf8ErrorKind::classify(invalid_sequence),
+    }
+}
+
+/// A push-based, lossy decoder for UTF-8 that emits one `char` at a time.
+/// Errors are replaced with the U+FFFD replacement character.
+///
+/// This is like `LossyDecoder`, except that the callback receives each `char`
+/// instead of `&str` slices, which suits char-driven consumers such as lexers.
+///
+/// **Note:** Dropping the decoder signals the end of the input:
+/// If the last input chunk ended with an incomplete byte sequence for a code point,
+/// this is an error and a replacement character is emitted.
+/// Use `std::mem::forget` to inhibit this behavior.
+#[derive(Clone)]
+pub struct CharDecoder<F: FnMut(char)> {
+    push_char: F,
+    incomplete: Incomplete,
+}
+
+impl<F: FnMut(char)> CharDecoder<F> {
+    /// Create a new decoder from a callback.
+    #[inline]
+    pub fn new(push_char: F) -> Self {
+        CharDecoder {
+            push_char,
+            incomplete: Incomplete::empty(),
+        }
+    }
+
+    /// Whether the decoder holds the start of a code point from previous input,
+    /// waiting for more input to complete it.
+    /// If so, dropping the decoder emits a replacement character.
+    #[inline]
+    pub fn has_incomplete(&self) -> bool {
+        !self.incomplete.is_empty()
+    }
+
+    /// Feed one chunk of input into the decoder.
+    ///
+    /// If the UTF-8 byte sequence for one code point was split into this bytes chunk
+    /// and previous bytes chunks, it will be correctly pieced back together.
+    pub fn feed(&mut self, input: &[u8]) {
+        let push_char = &mut self.push_char;
+        feed_lossy(&mut self.incomplete, input, ReplacementPolicy::MaximalSubpart,
+                   &mut |s| s.chars().for_each(&mut *push_char))
+    }
+}
+
+impl<F: FnMut(char)> Drop for CharDecoder<F> {
     #[inline]
     fn drop(&mut self) {
         if self.incomplete.buffer_len > 0 {
-            (self.push_str)(REPLACEMENT_CHARACTER)
+            (self.push_char)(REPLACEMENT_CHAR)
+        }
+    }
+}
+
+/// A lossy decoder for UTF-8 that appends to a `String` it owns.
+/// Errors are replaced with the U+FFFD replacement character.
+///
+/// This is like `LossyDecoder::to_buf`, without a borrow to manage:
+/// feed chunks, then read the decoded text with `as_str` or drain it with `take`.
+///
+/// ```rust
+/// let mut decoder = utf8::OwningDecoder::new();
+/// decoder.feed(b"caf\xC3");
+/// assert_eq!(decoder.take(), "caf");
+/// decoder.feed(b"\xA9");
+/// assert_eq!(decoder.take(), "é");
+/// ```
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct OwningDecoder {
+    output: String,
+    incomplete: Incomplete,
+}
+
+#[cfg(feature = "alloc")]
+impl OwningDecoder {
+    /// Create a new decoder with an empty output buffer.
+    #[inline]
+    pub fn new() -> Self {
+        OwningDecoder {
+            output: String::new(),
+            incomplete: Incomplete::empty(),
+        }
+    }
+
+    /// Feed one chunk of input into the decoder, appending its decoded text to the output.
+    ///
+    /// A byte sequence for one code point split across chunks is buffered
+    /// until the chunk that completes it.
+    pub fn feed(&mut self, input: &[u8]) {
+        let output = &mut self.output;
+        feed_lossy(&mut self.incomplete, input, ReplacementPolicy::MaximalSubpart,
+                   &mut |s| output.push_str(s))
+    }
+
+    /// Whether the decoder holds the start of a code point from previous input,
+    /// waiting for more input to complete it.
+    /// If so, `finish` appends a replacement character.
+    #[inline]
+    pub fn has_incomplete(&self) -> bool {
+        !self.incomplete.is_empty()
+    }
+
+    /// The text decoded since the last `take`.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.output
+    }
+
+    /// Return the text decoded since the last `take`, leaving the output empty.
+    /// A buffered incomplete sequence stays in the decoder.
+    #[inline]
+    pub fn take(&mut self) -> String {
+        core::mem::take(&mut self.output)
+    }
+
+    /// Signal the end of the input, and return the remaining output.
+    /// A buffered incomplete sequence is replaced with a replacement character.
+    pub fn finish(mut self) -> String {
+        if self.incomplete.buffer_len > 0 {
+            self.output.push(REPLACEMENT_CHAR)
         }
+        self.output
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Default for OwningDecoder {
+    #[inline]
+    fn default() -> Self {
+        OwningDecoder::new()
     }
 }
@@ -0,0 +1,137 @@
+//! [Modified UTF-8](https://docs.oracle.com/javase/specs/jvms/se8/html/jvms-4.html#jvms-4.4.7),
+//! as used by Java in class files, JNI, and `DataInput::readUTF`.
+//!
+//! It differs from standard UTF-8 in two ways:
+//!
+//! * U+0000 is encoded as the overlong two-byte sequence 0xC0 0x80.
+//! * Supplementary code points (above U+FFFF) are encoded as a surrogate pair
+//!   whose two halves are each encoded as a three-byte sequence, six bytes in total,
+//!   like in [CESU-8](https://www.unicode.org/reports/tr26/).
+//!
+//! The crate’s top-level `decode` keeps rejecting these sequences.
+
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str;
+use super::{decode as decode_utf8, DecodeError, Utf8ErrorKind};
+
+/// An error in modified UTF-8 input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ModifiedUtf8Error {
+    valid_up_to: usize,
+}
+
+impl ModifiedUtf8Error {
+    /// The length of the prefix of the input that was valid.
+    pub fn valid_up_to(&self) -> usize {
+        self.valid_up_to
+    }
+}
+
+impl fmt::Display for ModifiedUtf8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid modified UTF-8 after {} valid bytes", self.valid_up_to)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ModifiedUtf8Error {}
+
+/// Decode modified UTF-8 bytes.
+///
+/// Return `Cow::Borrowed` if the input is also valid standard UTF-8
+/// (no 0xC0 0x80 or surrogate pair), `Cow::Owned` otherwise.
+/// Unpaired surrogates are errors since they can not be represented in `str`.
+/// Standard four-byte sequences, which Java never emits, are accepted.
+pub fn decode(input: &[u8]) -> Result<Cow<'_, str>, ModifiedUtf8Error> {
+    let mut output = Cow::Borrowed("");
+    let mut position = 0;
+    loop {
+        let (valid_prefix, sequence) = match decode_utf8(&input[position..]) {
+            Ok(valid) => {
+                push(&mut output, valid);
+                return Ok(output)
+            }
+            Err(DecodeError::Incomplete { valid_prefix, .. }) => {
+                return Err(ModifiedUtf8Error { valid_up_to: position + valid_prefix.len() })
+            }
+            Err(DecodeError::Invalid { valid_prefix, kind, .. }) => {
+                let sequence = &input[position + valid_prefix.len()..];
+                let special_char = match kind {
+                    Utf8ErrorKind::Overlong if sequence.starts_with(b"\xC0\x80") => Some(('\0', 2)),
+                    Utf8ErrorKind::Surrogate => decode_surrogate_pair(sequence).map(|c| (c, 6)),
+                    _ => None,
+                };
+                (valid_prefix, special_char)
+            }
+        };
+        push(&mut output, valid_prefix);
+        position += valid_prefix.len();
+        match sequence {
+            Some((c, len)) => {
+                output.to_mut().push(c);
+                position += len;
+            }
+            None => return Err(ModifiedUtf8Error { valid_up_to: position }),
+        }
+    }
+}
+
+fn push<'a>(output: &mut Cow<'a, str>, s: &'a str) {
+    if output.is_empty() {
+        if let Cow::Borrowed(_) = *output {
+            *output = Cow::Borrowed(s);
+            return
+        }
+    }
+    if !s.is_empty() {
+        output.to_mut().push_str(s)
+    }
+}
+
+fn decode_surrogate_pair(bytes: &[u8]) -> Option<char> {
+    fn surrogate(bytes: &[u8]) -> Option<u32> {
+        match *bytes {
+            [0xED, second @ 0xA0..=0xBF, third @ 0x80..=0xBF, ..] => {
+                Some(0xD000 | ((second as u32 & 0x3F) << 6) | (third as u32 & 0x3F))
+            }
+            _ => None,
+        }
+    }
+    let lead = surrogate(bytes)?;
+    let trail = surrogate(bytes.get(3..)?)?;
+    match (lead, trail) {
+        (0xD800..=0xDBFF, 0xDC00..=0xDFFF) => {
+            core::char::from_u32(0x10000 + ((lead - 0xD800) << 10) + (trail - 0xDC00))
+        }
+        _ => None,
+    }
+}
+
+/// Encode a string as modified UTF-8.
+///
+/// Return `Cow::Borrowed` if the input contains no U+0000 or supplementary code point,
+/// since modified UTF-8 is then the same as standard UTF-8.
+pub fn encode(input: &str) -> Cow<'_, [u8]> {
+    let needs_escaping = |c: char| c == '\0' || c > '\u{FFFF}';
+    if !input.chars().any(needs_escaping) {
+        return Cow::Borrowed(input.as_bytes())
+    }
+    let mut output = Vec::with_capacity(input.len() + 8);
+    let mut utf16 = [0; 2];
+    for c in input.chars() {
+        if c == '\0' {
+            output.extend_from_slice(b"\xC0\x80")
+        } else if c > '\u{FFFF}' {
+            for &code_unit in c.encode_utf16(&mut utf16).iter() {
+                output.push(0xE0 | (code_unit >> 12) as u8);
+                output.push(0x80 | ((code_unit >> 6) & 0x3F) as u8);
+                output.push(0x80 | (code_unit & 0x3F) as u8);
+            }
+        } else {
+            output.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+        }
+    }
+    Cow::Owned(output)
+}
@@ -0,0 +1,23 @@
+//! The names needed for typical decoding tasks, for `use utf8::prelude::*;`.
+//!
+//! This covers decoding a buffer, strictly or lossily, and decoding a stream of chunks
+//! with `LossyDecoder`, `IncrementalDecoder`, or `BufReadDecoder`.
+//! More specialized APIs, such as the relaxed `Decoder` or UTF-16 output,
+//! are imported from the crate root.
+//!
+//! ```rust
+//! use utf8::prelude::*;
+//!
+//! let mut string = String::new();
+//! LossyDecoder::new(|s| string.push_str(s)).feed(b"caf\xC3\xA9 \xFF");
+//! assert_eq!(string, "café \u{FFFD}");
+//! assert!(matches!(decode(b"\xFF"), Err(DecodeError::Invalid { .. })));
+//! ```
+
+pub use super::{decode, DecodeError, Incomplete, Utf8ErrorKind};
+pub use super::{REPLACEMENT_CHAR, REPLACEMENT_CHARACTER};
+pub use super::{DecodedPiece, IncrementalDecoder, LossyDecoder};
+#[cfg(feature = "alloc")]
+pub use super::{decode_lossy, OwningDecoder};
+#[cfg(feature = "std")]
+pub use super::BufReadDecoder;
@@ -1,14 +1,58 @@
 use std::io::{self, BufRead};
 use std::error::Error;
 use std::fmt;
+use std::mem;
 use std::str;
+use std::string::String;
+use std::vec::Vec;
+#[cfg(any(feature = "futures", feature = "tokio"))]
+use std::task::{Context, Poll};
 use super::*;
 
+/// A buffered byte stream that `BufReadDecoder` can decode,
+/// for sources other than `std::io::BufRead` such as ring buffers.
+///
+/// This mirrors `BufRead::fill_buf` and `BufRead::consume`,
+/// and every `BufRead` type implements it.
+/// Method names differ from `BufRead`’s so that calls stay unambiguous
+/// with both traits in scope.
+pub trait Utf8Source {
+    /// Return the bytes available in the buffer, reading more if it is empty.
+    /// An empty slice means the end of the stream.
+    fn fill(&mut self) -> io::Result<&[u8]>;
+
+    /// Mark `n` bytes at the start of the buffer as decoded,
+    /// so that `fill` does not return them again.
+    fn consume_bytes(&mut self, n: usize);
+}
+
+impl<B: BufRead> Utf8Source for B {
+    #[inline]
+    fn fill(&mut self) -> io::Result<&[u8]> {
+        self.fill_buf()
+    }
+
+    #[inline]
+    fn consume_bytes(&mut self, n: usize) {
+        self.consume(n)
+    }
+}
+
 /// Wraps a `std::io::BufRead` buffered byte stream and decode it as UTF-8.
-pub struct BufReadDecoder<B: BufRead> {
+///
+/// Any other `Utf8Source` can be decoded the same way.
+#[derive(Clone)]
+pub struct BufReadDecoder<B: Utf8Source> {
     buf_read: B,
     bytes_consumed: usize,
+    stream_offset: usize,
     incomplete: Incomplete,
+    skip_bom: bool,
+    max_chunk: usize,
+    truncate_incomplete_silently: bool,
+    /// Replacement characters, and possibly the valid chunk after them, for `next_lossy_coalesced`.
+    coalesced: String,
+    coalesced_returned: bool,
 }
 
 #[derive(Debug)]
@@ -17,7 +61,15 @@ pub enum BufReadDecoderError<'a> {
     ///
     /// In lossy decoding, each such error should be replaced with U+FFFD.
     /// (See `BufReadDecoder::next_lossy` and `BufReadDecoderError::lossy`.)
-    InvalidByteSequence(&'a [u8]),
+    InvalidByteSequence {
+        invalid_sequence: &'a [u8],
+        /// The position of `invalid_sequence` in the overall byte stream.
+        byte_offset: usize,
+        /// Whether the stream ended in the middle of `invalid_sequence`,
+        /// which more input could have completed,
+        /// rather than it being followed by a byte that can’t continue it.
+        incomplete: bool,
+    },
 
     /// An I/O error from the underlying byte stream
     Io(io::Error),
@@ -28,7 +80,7 @@ impl<'a> BufReadDecoderError<'a> {
     pub fn lossy(self) -> Result<&'static str, io::Error> {
         match self {
             BufReadDecoderError::Io(error) => Err(error),
-            BufReadDecoderError::InvalidByteSequence(_) => Ok(REPLACEMENT_CHARACTER),
+            BufReadDecoderError::InvalidByteSequence { .. } => Ok(REPLACEMENT_CHARACTER),
         }
     }
 }
@@ -36,8 +88,11 @@ impl<'a> BufReadDecoderError<'a> {
 impl<'a> fmt::Display for BufReadDecoderError<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            BufReadDecoderError::InvalidByteSequence(bytes) => {
-                write!(f, "invalid byte sequence: {:02x?}", bytes)
+            BufReadDecoderError::InvalidByteSequence { invalid_sequence, byte_offset,
+                                                       incomplete } => {
+                write!(f, "{} byte sequence {:02x?} at byte offset {}",
+                       if incomplete { "incomplete" } else { "invalid" },
+                       invalid_sequence, byte_offset)
             }
             BufReadDecoderError::Io(ref err) => write!(f, "underlying bytestream error: {}", err),
         }
@@ -47,13 +102,13 @@ impl<'a> fmt::Display for BufReadDecoderError<'a> {
 impl<'a> Error for BufReadDecoderError<'a> {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match *self {
-            BufReadDecoderError::InvalidByteSequence(_) => None,
+            BufReadDecoderError::InvalidByteSequence { .. } => None,
             BufReadDecoderError::Io(ref err) => Some(err),
         }
     }
 }
 
-impl<B: BufRead> BufReadDecoder<B> {
+impl<B: Utf8Source> BufReadDecoder<B> {
     /// This is to `Read::read_to_string` what `String::from_utf8_lossy` is to `String::from_utf8`.
     pub fn read_to_string_lossy(buf_read: B) -> io::Result<String> {
         let mut decoder = Self::new(buf_read);
@@ -68,7 +123,155 @@ impl<B: BufRead> BufReadDecoder<B> {
         Self {
             buf_read,
             bytes_consumed: 0,
+            stream_offset: 0,
             incomplete: Incomplete::empty(),
+            skip_bom: false,
+            max_chunk: usize::MAX,
+            truncate_incomplete_silently: false,
+            coalesced: String::new(),
+            coalesced_returned: false,
+        }
+    }
+
+    /// Create a decoder whose valid chunks are at most `max_chunk` bytes,
+    /// regardless of the size of the underlying reader’s buffer.
+    ///
+    /// Chunks end on a code point boundary, so they can be shorter.
+    /// A chunk is only longer than `max_chunk` if that is less than
+    /// the length of its one code point.
+    pub fn with_max_chunk(buf_read: B, max_chunk: usize) -> Self {
+        let mut decoder = Self::new(buf_read);
+        decoder.max_chunk = max_chunk;
+        decoder
+    }
+
+    /// Whether to skip a byte order mark (U+FEFF, bytes 0xEF 0xBB 0xBF)
+    /// at the very start of the stream. It is never skipped elsewhere.
+    ///
+    /// This is disabled by default, and has no effect once a chunk has been returned.
+    pub fn skip_bom(mut self, skip: bool) -> Self {
+        self.skip_bom = skip && self.stream_offset == 0;
+        self
+    }
+
+    /// Whether to drop an incomplete sequence at the end of the stream silently,
+    /// as for input cut off in the middle of a code point,
+    /// instead of returning it as an error.
+    ///
+    /// This is disabled by default. Invalid sequences are always returned as errors.
+    /// Dropped bytes are not counted in `stream_offset`.
+    pub fn truncate_incomplete_silently(mut self, truncate: bool) -> Self {
+        self.truncate_incomplete_silently = truncate;
+        self
+    }
+
+    /// Decode and append to `string` the rest of the input, stopping at the first error.
+    ///
+    /// This is to `Read::read_to_string` what `next_strict` is to `next_lossy`.
+    /// On success, return the number of bytes appended.
+    /// On error, text decoded before the error is kept in `string`,
+    /// and the decoder is positioned just after the invalid byte sequence,
+    /// so that decoding can resume with another call.
+    pub fn read_to_string_strict(&mut self, string: &mut String)
+                                 -> Result<usize, BufReadDecoderError<'_>> {
+        let initial_len = string.len();
+        // Not using `next_strict`: returning its borrowed error from the loop would not borrow-check.
+        while let Some(next) = self.next_source() {
+            let (source, result) = next.map_err(BufReadDecoderError::Io)?;
+            let byte_offset = self.stream_offset;
+            match result {
+                Ok(()) => {
+                    let bytes = self.source_bytes(source).map_err(BufReadDecoderError::Io)?;
+                    string.push_str(unsafe { str::from_utf8_unchecked(bytes) })
+                }
+                Err(()) => {
+                    let incomplete = source.is_truncated();
+                    let invalid_sequence = self.source_bytes(source)
+                        .map_err(BufReadDecoderError::Io)?;
+                    return Err(BufReadDecoderError::InvalidByteSequence {
+                        invalid_sequence,
+                        byte_offset,
+                        incomplete,
+                    })
+                }
+            }
+        }
+        Ok(string.len() - initial_len)
+    }
+
+    /// Decode and append to `string` the input up to and including the next `\n`,
+    /// or to the end of the stream, replacing UTF-8 errors with U+FFFD.
+    ///
+    /// This is to `BufRead::read_line` what `next_lossy` is to `next_strict`.
+    /// Return the number of bytes appended, which is 0 at the end of the stream.
+    /// It can differ from the number of input bytes decoded, because of replacements.
+    pub fn read_line_lossy(&mut self, string: &mut String) -> io::Result<usize> {
+        let initial_len = string.len();
+        while let Some(next) = self.next_source() {
+            let (source, result) = next?;
+            if result.is_err() {
+                self.source_bytes(source)?;
+                string.push_str(REPLACEMENT_CHARACTER);
+                continue
+            }
+            let newline = match source {
+                ChunkSource::BufRead(byte_count) => {
+                    self.buf_read.fill()?[..byte_count].iter().position(|&b| b == b'\n')
+                }
+                ChunkSource::Incomplete { .. } => {
+                    self.incomplete.buffered_bytes().iter().position(|&b| b == b'\n')
+                }
+            };
+            let source = match newline {
+                Some(position) => self.shorten_source(source, position + 1),
+                None => source,
+            };
+            string.push_str(unsafe { str::from_utf8_unchecked(self.source_bytes(source)?) });
+            if newline.is_some() {
+                break
+            }
+        }
+        Ok(string.len() - initial_len)
+    }
+
+    /// The number of bytes of the underlying stream decoded so far,
+    /// up to the end of the chunk most recently returned.
+    pub fn stream_offset(&self) -> usize {
+        self.stream_offset
+    }
+
+    /// Gets a reference to the underlying reader.
+    ///
+    /// Bytes of the chunk most recently returned by `next_strict` or `next_lossy`
+    /// may not have been consumed from it yet.
+    pub fn get_ref(&self) -> &B {
+        &self.buf_read
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    ///
+    /// Bytes of the chunk most recently returned are consumed first,
+    /// so that reading from the returned reference starts right after decoded input.
+    pub fn get_mut(&mut self) -> &mut B {
+        self.consume_pending();
+        &mut self.buf_read
+    }
+
+    /// Unwraps this decoder, returning the underlying reader.
+    ///
+    /// Bytes of the chunk most recently returned are consumed first.
+    ///
+    /// **Note:** any bytes of an incomplete code point buffered by the decoder
+    /// have already been consumed from the reader and are lost.
+    pub fn into_inner(mut self) -> B {
+        self.consume_pending();
+        self.buf_read
+    }
+
+    fn consume_pending(&mut self) {
+        if self.bytes_consumed > 0 {
+            self.buf_read.consume_bytes(self.bytes_consumed);
+            self.bytes_consumed = 0;
         }
     }
 
@@ -77,6 +280,53 @@ impl<B: BufRead> BufReadDecoder<B> {
         self.next_strict().map(|result| result.or_else(|e| e.lossy()))
     }
 
+    /// Same as `BufReadDecoder::next_lossy`, but with fewer, larger chunks:
+    /// consecutive errors are merged into one chunk of replacement characters,
+    /// together with the valid chunk that follows them if any.
+    ///
+    /// There is still one U+FFFD per error.
+    /// Valid chunks with no error before them are returned without copying.
+    pub fn next_lossy_coalesced(&mut self) -> Option<io::Result<&str>> {
+        if self.coalesced_returned {
+            self.coalesced.clear();
+            self.coalesced_returned = false;
+        }
+        // On I/O errors, `self.coalesced` is kept for the next call.
+        while let Some(next) = self.next_source() {
+            let (source, result) = match next {
+                Ok(next) => next,
+                Err(error) => return Some(Err(error)),
+            };
+            match result {
+                Ok(()) if self.coalesced.is_empty() => {
+                    return Some(self.source_bytes(source)
+                        .map(|bytes| unsafe { str::from_utf8_unchecked(bytes) }))
+                }
+                Ok(()) => {
+                    let mut coalesced = mem::take(&mut self.coalesced);
+                    let pushed = self.source_bytes(source)
+                        .map(|bytes| coalesced.push_str(unsafe { str::from_utf8_unchecked(bytes) }));
+                    self.coalesced = coalesced;
+                    match pushed {
+                        Ok(()) => break,
+                        Err(error) => return Some(Err(error)),
+                    }
+                }
+                Err(()) => {
+                    if let Err(error) = self.source_bytes(source) {
+                        return Some(Err(error))
+                    }
+                    self.coalesced.push_str(REPLACEMENT_CHARACTER)
+                }
+            }
+        }
+        if self.coalesced.is_empty() {
+            return None
+        }
+        self.coalesced_returned = true;
+        Some(Ok(&self.coalesced))
+    }
+
     /// Decode and consume the next chunk of UTF-8 input.
     ///
     /// This method is intended to be called repeatedly until it returns `None`,
@@ -84,84 +334,394 @@ impl<B: BufRead> BufReadDecoder<B> {
     /// This is similar to `Iterator::next`,
     /// except that decoded chunks borrow the decoder (~iterator)
     /// so they need to be handled or copied before the next chunk can start decoding.
-    pub fn next_strict(&mut self) -> Option<Result<&str, BufReadDecoderError>> {
-        enum BytesSource {
-            BufRead(usize),
-            Incomplete,
+    pub fn next_strict(&mut self) -> Option<Result<&str, BufReadDecoderError<'_>>> {
+        let (source, result) = match self.next_source()? {
+            Ok(next) => next,
+            Err(error) => return Some(Err(BufReadDecoderError::Io(error))),
+        };
+        let byte_offset = self.stream_offset;
+        let incomplete = source.is_truncated();
+        let bytes = match self.source_bytes(source) {
+            Ok(bytes) => bytes,
+            Err(error) => return Some(Err(BufReadDecoderError::Io(error))),
+        };
+        match result {
+            Ok(()) => Some(Ok(unsafe { str::from_utf8_unchecked(bytes) })),
+            Err(()) => Some(Err(BufReadDecoderError::InvalidByteSequence {
+                invalid_sequence: bytes,
+                byte_offset,
+                incomplete,
+            })),
         }
+    }
+
+    /// Find where the next chunk of input is, and whether it is valid UTF-8.
+    fn next_source(&mut self) -> Option<io::Result<(ChunkSource, Result<(), ()>)>> {
         macro_rules! try_io {
             ($io_result: expr) => {
                 match $io_result {
                     Ok(value) => value,
-                    Err(error) => return Some(Err(BufReadDecoderError::Io(error)))
+                    Err(error) => return Some(Err(error))
                 }
             }
         }
         let (source, result) = loop {
-            if self.bytes_consumed > 0 {
-                self.buf_read.consume(self.bytes_consumed);
-                self.bytes_consumed = 0;
+            self.consume_pending();
+            let buf = try_io!(self.buf_read.fill());
+            if buf.is_empty() && self.truncate_incomplete_silently {
+                self.incomplete = Incomplete::empty();
             }
-            let buf = try_io!(self.buf_read.fill_buf());
-
-            // Force loop iteration to go through an explicit `continue`
-            enum Unreachable {}
-            let _: Unreachable = if self.incomplete.is_empty() {
-                if buf.is_empty() {
-                    return None  // EOF
-                }
-                match str::from_utf8(buf) {
-                    Ok(_) => {
-                        break (BytesSource::BufRead(buf.len()), Ok(()))
-                    }
-                    Err(error) => {
-                        let valid_up_to = error.valid_up_to();
-                        if valid_up_to > 0 {
-                            break (BytesSource::BufRead(valid_up_to), Ok(()))
+            match next_step(&mut self.incomplete, buf) {
+                Step::Eof => return None,
+                Step::NeedMore { consumed } => self.bytes_consumed = consumed,
+                Step::Chunk { source, result, consumed } => {
+                    self.bytes_consumed = consumed;
+                    match source {
+                        BytesSource::BufRead(byte_count) if result.is_err() => {
+                            break (ChunkSource::BufRead(byte_count), result)
+                        }
+                        BytesSource::BufRead(byte_count) => {
+                            let len = chunk_len(&buf[..byte_count], self.max_chunk);
+                            break (ChunkSource::BufRead(len), result)
+                        }
+                        BytesSource::Incomplete if result.is_err() => {
+                            break (ChunkSource::Incomplete { at_eof: buf.is_empty() }, result)
                         }
-                        match error.error_len() {
-                            Some(invalid_sequence_length) => {
-                                break (BytesSource::BufRead(invalid_sequence_length), Err(()))
-                            }
-                            None => {
-                                self.bytes_consumed = buf.len();
-                                self.incomplete = Incomplete::new(buf);
-                                // need more input bytes
-                                continue
-                            }
+                        BytesSource::Incomplete => {
+                            let len = chunk_len(self.incomplete.buffered_bytes(), self.max_chunk);
+                            let source = ChunkSource::Incomplete { at_eof: false };
+                            break (self.shorten_source(source, len), result)
                         }
                     }
                 }
-            } else {
-                if buf.is_empty() {
-                    break (BytesSource::Incomplete, Err(()))  // EOF with incomplete code point
+            }
+        };
+        if self.skip_bom {
+            self.skip_bom = false;
+            if result.is_ok() && try_io!(self.skip_leading_bom(&source)) {
+                return self.next_source()
+            }
+        }
+        Some(Ok((source, result)))
+    }
+
+    /// If the chunk at `source` starts with a byte order mark, skip it.
+    /// Return whether `next_source` needs to be called again.
+    fn skip_leading_bom(&mut self, source: &ChunkSource) -> io::Result<bool> {
+        match *source {
+            ChunkSource::BufRead(_) => {
+                if !self.buf_read.fill()?.starts_with(UTF8_BOM) {
+                    return Ok(false)
                 }
-                let (consumed, opt_result) = self.incomplete.try_complete_offsets(buf);
-                self.bytes_consumed = consumed;
-                match opt_result {
-                    None => {
-                        // need more input bytes
-                        continue
-                    }
-                    Some(result) => {
-                        break (BytesSource::Incomplete, result)
-                    }
+                self.bytes_consumed = UTF8_BOM.len();
+                self.stream_offset += UTF8_BOM.len();
+                Ok(true)
+            }
+            ChunkSource::Incomplete { .. } => {
+                // The buffer may contain more complete code points after the BOM
+                let buffer = self.incomplete.buffer;
+                let buffer = &buffer[..self.incomplete.buffer_len as usize];
+                if !buffer.starts_with(UTF8_BOM) {
+                    return Ok(false)
                 }
-            };
-        };
+                self.incomplete = Incomplete::new(&buffer[UTF8_BOM.len()..]);
+                self.stream_offset += UTF8_BOM.len();
+                Ok(self.incomplete.is_empty())
+            }
+        }
+    }
+
+    /// Keep only the first `len` bytes of a valid chunk found by `next_source`,
+    /// which must be a code point boundary, leaving the rest to be decoded next.
+    fn shorten_source(&mut self, source: ChunkSource, len: usize) -> ChunkSource {
+        match source {
+            ChunkSource::BufRead(_) => ChunkSource::BufRead(len),
+            ChunkSource::Incomplete { at_eof } => {
+                // Code points after the completed one came from `buf`: leave them there.
+                let buffered_len = self.incomplete.buffer_len as usize;
+                self.bytes_consumed -= buffered_len - len;
+                self.incomplete.buffer_len = len as u8;
+                ChunkSource::Incomplete { at_eof }
+            }
+        }
+    }
+
+    /// Take the bytes of a chunk found by `next_source`.
+    fn source_bytes(&mut self, source: ChunkSource) -> io::Result<&[u8]> {
         let bytes = match source {
-            BytesSource::BufRead(byte_count) => {
+            ChunkSource::BufRead(byte_count) => {
                 self.bytes_consumed = byte_count;
-                let buf = try_io!(self.buf_read.fill_buf());
+                let buf = self.buf_read.fill()?;
                 &buf[..byte_count]
             }
-            BytesSource::Incomplete => {
+            ChunkSource::Incomplete { .. } => {
                 self.incomplete.take_buffer()
             }
         };
-        match result {
-            Ok(()) => Some(Ok(unsafe { str::from_utf8_unchecked(bytes) })),
-            Err(()) => Some(Err(BufReadDecoderError::InvalidByteSequence(bytes))),
+        self.stream_offset += bytes.len();
+        Ok(bytes)
+    }
+}
+
+impl<B: BufRead> BufReadDecoder<MinFill<B>> {
+    /// Create a decoder that accumulates at least `min_fill` bytes
+    /// (or up to the end of the stream) before decoding them.
+    ///
+    /// This helps with readers that return very few bytes at a time:
+    /// without it, such a reader returning one byte at a time makes
+    /// `next_lossy` and `next_strict` return one code point per call.
+    /// The first fill is always accumulated. After that, readers that return
+    /// at least `min_fill` bytes at once are used without copying.
+    pub fn with_min_fill(buf_read: B, min_fill: usize) -> Self {
+        Self::new(MinFill::new(buf_read, min_fill))
+    }
+}
+
+impl<R: io::Read> BufReadDecoder<io::BufReader<R>> {
+    /// Create a decoder for a reader that is not buffered, such as `File` or `TcpStream`,
+    /// by wrapping it in a `BufReader` with the default capacity.
+    pub fn from_read(read: R) -> Self {
+        Self::new(io::BufReader::new(read))
+    }
+
+    /// Same as `from_read`, with a `BufReader` of `capacity` bytes.
+    ///
+    /// Valid chunks returned by `next_strict` come from one fill of the buffer,
+    /// so they are at most `capacity` bytes,
+    /// or up to 4 bytes when they complete a code point split across fills.
+    /// A larger capacity gives fewer, larger chunks. Use `with_max_chunk` to bound them
+    /// independently of the buffer.
+    pub fn from_read_with_capacity(read: R, capacity: usize) -> Self {
+        Self::new(io::BufReader::with_capacity(capacity, read))
+    }
+}
+
+/// A `BufRead` adapter that accumulates bytes from the underlying reader
+/// until at least some minimum number are available in one buffer.
+/// See `BufReadDecoder::with_min_fill`.
+///
+/// The first fill is always accumulated.
+/// Once the underlying reader returns that many bytes at once, its buffer is used directly
+/// and each fill calls its `fill_buf` once. A shorter fill from it is then returned as is,
+/// and the following fills accumulate again.
+#[derive(Debug, Clone)]
+pub struct MinFill<B: BufRead> {
+    buf_read: B,
+    min_fill: usize,
+    buffer: Vec<u8>,
+    position: usize,
+    /// Whether the last fill of the underlying reader had at least `min_fill` bytes.
+    pass_through: bool,
+}
+
+impl<B: BufRead> MinFill<B> {
+    pub fn new(buf_read: B, min_fill: usize) -> Self {
+        MinFill {
+            buf_read,
+            min_fill,
+            buffer: Vec::new(),
+            position: 0,
+            pass_through: false,
+        }
+    }
+
+    /// Unwraps this adapter, returning the underlying reader.
+    ///
+    /// **Note:** any bytes accumulated but not consumed yet are lost.
+    pub fn into_inner(self) -> B {
+        self.buf_read
+    }
+}
+
+impl<B: BufRead> io::Read for MinFill<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = {
+            let available = self.fill_buf()?;
+            let len = available.len().min(buf.len());
+            buf[..len].copy_from_slice(&available[..len]);
+            len
+        };
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<B: BufRead> BufRead for MinFill<B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position == self.buffer.len() {
+            if self.pass_through {
+                // Use the underlying buffer directly. Checking its length first
+                // and then returning it would take a second `fill_buf` call.
+                let buf = self.buf_read.fill_buf()?;
+                self.pass_through = buf.is_empty() || buf.len() >= self.min_fill;
+                return Ok(buf)
+            }
+            self.buffer.clear();
+            self.position = 0;
+        }
+        while self.buffer.len() - self.position < self.min_fill {
+            let len = {
+                let buf = self.buf_read.fill_buf()?;
+                self.buffer.extend_from_slice(buf);
+                buf.len()
+            };
+            if len == 0 {
+                break
+            }
+            self.pass_through = len >= self.min_fill;
+            self.buf_read.consume(len);
+        }
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.position < self.buffer.len() {
+            self.position += amt;
+        } else {
+            self.buf_read.consume(amt)
+        }
+    }
+}
+
+/// The length of the longest prefix of valid `chunk` that is at most `max` bytes
+/// and ends on a code point boundary, but at least one code point.
+fn chunk_len(chunk: &[u8], max: usize) -> usize {
+    if chunk.len() <= max {
+        return chunk.len()
+    }
+    match (1..=max).rev().find(|&len| utf8_char_width(chunk[len]) != 0) {
+        Some(len) => len,
+        None => utf8_char_width(chunk[0]),
+    }
+}
+
+/// Where the bytes of the chunk found by `BufReadDecoder::next_source` are.
+enum ChunkSource {
+    /// The first bytes of the reader’s buffer.
+    ///
+    /// The slice is taken again from `fill` when needed:
+    /// the borrow checker rejects returning a slice borrowed from `buf_read`
+    /// out of a loop that also consumes from it.
+    BufRead(usize),
+
+    /// In `Incomplete`’s buffer, which can only be an error at the end of the stream
+    /// if `at_eof`.
+    Incomplete { at_eof: bool },
+}
+
+impl ChunkSource {
+    /// Whether this chunk is an error because the stream ended in the middle of a code point.
+    fn is_truncated(&self) -> bool {
+        match *self {
+            ChunkSource::Incomplete { at_eof } => at_eof,
+            ChunkSource::BufRead(_) => false,
+        }
+    }
+}
+
+pub(crate) enum BytesSource {
+    BufRead(usize),
+    Incomplete,
+}
+
+/// What to do next after looking at bytes buffered in the underlying reader.
+pub(crate) enum Step {
+    /// End of the input.
+    Eof,
+
+    /// Consume `consumed` bytes from the reader, and look at its buffer again.
+    NeedMore { consumed: usize },
+
+    /// The next chunk is found. Consume `consumed` bytes from the reader first;
+    /// for `BytesSource::BufRead`, the chunk’s bytes are still in the reader’s buffer.
+    Chunk { source: BytesSource, result: Result<(), ()>, consumed: usize },
+}
+
+/// The decoding state machine of `BufReadDecoder`, independent of how `buf` is obtained.
+/// `buf` is the not-yet-consumed content of the reader’s buffer, empty at EOF.
+pub(crate) fn next_step(incomplete: &mut Incomplete, buf: &[u8]) -> Step {
+    if incomplete.is_empty() {
+        if buf.is_empty() {
+            return Step::Eof
+        }
+        let (source, result) = match utf8_error(buf) {
+            None => (BytesSource::BufRead(buf.len()), Ok(())),
+            Some((valid_up_to, error_len)) => {
+                if valid_up_to > 0 {
+                    (BytesSource::BufRead(valid_up_to), Ok(()))
+                } else {
+                    match error_len {
+                        Some(invalid_sequence_length) => {
+                            (BytesSource::BufRead(invalid_sequence_length), Err(()))
+                        }
+                        None => {
+                            *incomplete = Incomplete::new(buf);
+                            // need more input bytes
+                            return Step::NeedMore { consumed: buf.len() }
+                        }
+                    }
+                }
+            }
+        };
+        Step::Chunk { source, result, consumed: 0 }
+    } else {
+        if buf.is_empty() {
+            // EOF with incomplete code point
+            return Step::Chunk { source: BytesSource::Incomplete, result: Err(()), consumed: 0 }
+        }
+        let (consumed, opt_result) = incomplete.try_complete_offsets(buf);
+        match opt_result {
+            // need more input bytes
+            None => Step::NeedMore { consumed },
+            Some(result) => Step::Chunk { source: BytesSource::Incomplete, result, consumed },
+        }
+    }
+}
+
+/// The buffer-related methods shared by the `AsyncBufRead` traits of `futures` and `tokio`.
+#[cfg(any(feature = "futures", feature = "tokio"))]
+pub(crate) trait PollBufRead {
+    fn poll_fill_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>>;
+    fn consume(&mut self, amt: usize);
+}
+
+/// The polling loop of the async adapters: like `BufReadDecoder::next_lossy`,
+/// but yielding owned strings since the reader’s buffer can’t stay borrowed across polls.
+/// Returning `Pending` keeps the `Incomplete` state for the next call.
+#[cfg(any(feature = "futures", feature = "tokio"))]
+pub(crate) fn poll_next_lossy<R: PollBufRead>(reader: &mut R, bytes_consumed: &mut usize,
+                                              incomplete: &mut Incomplete, cx: &mut Context<'_>)
+                                              -> Poll<io::Result<Option<String>>> {
+    loop {
+        if *bytes_consumed > 0 {
+            reader.consume(*bytes_consumed);
+            *bytes_consumed = 0;
+        }
+        let buf = match reader.poll_fill_buf(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+            Poll::Ready(Ok(buf)) => buf,
+        };
+        match next_step(incomplete, buf) {
+            Step::Eof => return Poll::Ready(Ok(None)),
+            Step::NeedMore { consumed } => *bytes_consumed = consumed,
+            Step::Chunk { source, result, consumed } => {
+                let bytes = match source {
+                    BytesSource::BufRead(byte_count) => {
+                        *bytes_consumed = consumed + byte_count;
+                        &buf[..byte_count]
+                    }
+                    BytesSource::Incomplete => {
+                        *bytes_consumed = consumed;
+                        incomplete.take_buffer()
+                    }
+                };
+                let chunk = match result {
+                    Ok(()) => unsafe { str::from_utf8_unchecked(bytes) },
+                    Err(()) => REPLACEMENT_CHARACTER,
+                };
+                return Poll::Ready(Ok(Some(chunk.into())))
+            }
         }
     }
 }
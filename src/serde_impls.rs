@@ -0,0 +1,64 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+use super::*;
+
+/// A `String` that deserializes from bytes that are *supposed* to be UTF-8,
+/// replacing errors with the U+FFFD replacement character like `decode_lossy`
+/// rather than failing.
+///
+/// Strings, byte strings, and sequences of bytes are accepted.
+/// Serialization writes the inner string.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct LossyString(pub String);
+
+impl Serialize for LossyString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for LossyString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_byte_buf(LossyStringVisitor)
+    }
+}
+
+struct LossyStringVisitor;
+
+impl<'de> Visitor<'de> for LossyStringVisitor {
+    type Value = LossyString;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a string or bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<LossyString, E> {
+        Ok(LossyString(v.into()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<LossyString, E> {
+        Ok(LossyString(v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<LossyString, E> {
+        Ok(LossyString(decode_lossy(v).into_owned()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<LossyString, E> {
+        // Reuse the allocation when the input is valid.
+        match String::from_utf8(v) {
+            Ok(s) => Ok(LossyString(s)),
+            Err(error) => self.visit_bytes(error.as_bytes()),
+        }
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<LossyString, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte)
+        }
+        self.visit_byte_buf(bytes)
+    }
+}
@@ -0,0 +1,64 @@
+use futures_core::Stream;
+use futures_io::AsyncBufRead;
+use std::io;
+use std::pin::Pin;
+use std::string::String;
+use std::task::{Context, Poll};
+use read::{poll_next_lossy, PollBufRead};
+use super::*;
+
+/// Wraps a `futures::io::AsyncBufRead` buffered byte stream and decodes it as UTF-8, lossily.
+///
+/// This is a `Stream` of owned `String` chunks, split the same way as
+/// the results of `BufReadDecoder::next_lossy`:
+/// each chunk is either valid input, or an U+FFFD replacement character for one error.
+/// A code point split across buffers of the underlying reader is pieced back together
+/// and never yielded in parts.
+pub struct AsyncUtf8Stream<R: AsyncBufRead + Unpin> {
+    reader: R,
+    bytes_consumed: usize,
+    incomplete: Incomplete,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncUtf8Stream<R> {
+    pub fn new(reader: R) -> Self {
+        AsyncUtf8Stream {
+            reader,
+            bytes_consumed: 0,
+            incomplete: Incomplete::empty(),
+        }
+    }
+
+    /// Unwraps this stream, returning the underlying reader.
+    ///
+    /// **Note:** any bytes of an incomplete code point buffered by the decoder
+    /// have already been consumed from the reader and are lost.
+    pub fn into_inner(mut self) -> R {
+        if self.bytes_consumed > 0 {
+            Pin::new(&mut self.reader).consume(self.bytes_consumed);
+        }
+        self.reader
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> Stream for AsyncUtf8Stream<R> {
+    type Item = io::Result<String>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        poll_next_lossy(&mut Reader(&mut this.reader), &mut this.bytes_consumed,
+                        &mut this.incomplete, cx).map(Result::transpose)
+    }
+}
+
+struct Reader<'a, R: 'a>(&'a mut R);
+
+impl<'a, R: AsyncBufRead + Unpin> PollBufRead for Reader<'a, R> {
+    fn poll_fill_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut *self.0).poll_fill_buf(cx)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        Pin::new(&mut *self.0).consume(amt)
+    }
+}
@@ -0,0 +1,77 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::string::String;
+use std::task::{Context, Poll};
+use tokio::io::AsyncBufRead;
+use read::{poll_next_lossy, PollBufRead};
+use super::*;
+
+/// Wraps a `tokio::io::AsyncBufRead` buffered byte stream and decodes it as UTF-8, lossily.
+///
+/// Chunks are split the same way as the results of `BufReadDecoder::next_lossy`,
+/// but are owned `String`s since the reader’s buffer can’t stay borrowed across `.await`.
+pub struct TokioUtf8Decoder<R: AsyncBufRead + Unpin> {
+    reader: R,
+    bytes_consumed: usize,
+    incomplete: Incomplete,
+}
+
+impl<R: AsyncBufRead + Unpin> TokioUtf8Decoder<R> {
+    pub fn new(reader: R) -> Self {
+        TokioUtf8Decoder {
+            reader,
+            bytes_consumed: 0,
+            incomplete: Incomplete::empty(),
+        }
+    }
+
+    /// Decode and consume the next chunk of UTF-8 input.
+    ///
+    /// The returned future resolves to
+    /// either valid input, or an U+FFFD replacement character for one error,
+    /// or `None` at the end of the input.
+    /// A code point split across buffers of the underlying reader
+    /// is kept across await points and never returned in parts.
+    pub fn next_lossy(&mut self) -> NextLossy<'_, R> {
+        NextLossy { decoder: self }
+    }
+
+    /// Unwraps this decoder, returning the underlying reader.
+    ///
+    /// **Note:** any bytes of an incomplete code point buffered by the decoder
+    /// have already been consumed from the reader and are lost.
+    pub fn into_inner(mut self) -> R {
+        if self.bytes_consumed > 0 {
+            Pin::new(&mut self.reader).consume(self.bytes_consumed);
+        }
+        self.reader
+    }
+}
+
+/// The future returned by `TokioUtf8Decoder::next_lossy`.
+pub struct NextLossy<'a, R: AsyncBufRead + Unpin + 'a> {
+    decoder: &'a mut TokioUtf8Decoder<R>,
+}
+
+impl<'a, R: AsyncBufRead + Unpin> Future for NextLossy<'a, R> {
+    type Output = io::Result<Option<String>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let decoder = &mut *self.get_mut().decoder;
+        poll_next_lossy(&mut Reader(&mut decoder.reader), &mut decoder.bytes_consumed,
+                        &mut decoder.incomplete, cx)
+    }
+}
+
+struct Reader<'a, R: 'a>(&'a mut R);
+
+impl<'a, R: AsyncBufRead + Unpin> PollBufRead for Reader<'a, R> {
+    fn poll_fill_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut *self.0).poll_fill_buf(cx)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        Pin::new(&mut *self.0).consume(amt)
+    }
+}
@@ -0,0 +1,152 @@
+use super::*;
+use lossy::{feed_lossy, ReplacementPolicy};
+
+/// A push-based, lossy transcoder from UTF-8 to UTF-16.
+/// Errors are replaced with the U+FFFD replacement character, as a single code unit.
+///
+/// This is like `LossyDecoder`, except that the callback receives `&[u16]` slices of
+/// UTF-16 code units instead of `&str`. A surrogate pair is never split across two slices.
+///
+/// **Note:** Dropping the transcoder signals the end of the input:
+/// If the last input chunk ended with an incomplete byte sequence for a code point,
+/// this is an error and a replacement character is emitted.
+/// Use `std::mem::forget` to inhibit this behavior.
+#[derive(Clone)]
+pub struct Utf8ToUtf16<F: FnMut(&[u16])> {
+    push_utf16: F,
+    incomplete: Incomplete,
+}
+
+impl<F: FnMut(&[u16])> Utf8ToUtf16<F> {
+    /// Create a new transcoder from a callback.
+    #[inline]
+    pub fn new(push_utf16: F) -> Self {
+        Utf8ToUtf16 {
+            push_utf16,
+            incomplete: Incomplete::empty(),
+        }
+    }
+
+    /// Whether the transcoder holds the start of a code point from previous input,
+    /// waiting for more input to complete it.
+    /// If so, dropping the transcoder emits a replacement character.
+    #[inline]
+    pub fn has_incomplete(&self) -> bool {
+        !self.incomplete.is_empty()
+    }
+
+    /// Feed one chunk of UTF-8 input into the transcoder.
+    ///
+    /// If the UTF-8 byte sequence for one code point was split into this bytes chunk
+    /// and previous bytes chunks, it will be correctly pieced back together.
+    pub fn feed(&mut self, input: &[u8]) {
+        let push_utf16 = &mut self.push_utf16;
+        feed_lossy(&mut self.incomplete, input, ReplacementPolicy::MaximalSubpart,
+                   &mut |s| encode_utf16(s, push_utf16))
+    }
+}
+
+impl<F: FnMut(&[u16])> Drop for Utf8ToUtf16<F> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.incomplete.buffer_len > 0 {
+            (self.push_utf16)(&[REPLACEMENT_CHAR as u16])
+        }
+    }
+}
+
+/// Transcode `s` into the callback through a stack buffer.
+fn encode_utf16<F: FnMut(&[u16])>(s: &str, push_utf16: &mut F) {
+    let mut buffer = [0_u16; 256];
+    let mut len = 0;
+    for c in s.chars() {
+        if buffer.len() - len < 2 {
+            push_utf16(&buffer[..len]);
+            len = 0;
+        }
+        len += c.encode_utf16(&mut buffer[len..]).len();
+    }
+    if len > 0 {
+        push_utf16(&buffer[..len])
+    }
+}
+
+/// A push-based, lossy decoder for UTF-16.
+/// Unpaired surrogates are replaced with the U+FFFD replacement character.
+///
+/// This is like `LossyDecoder`, except that input chunks are `&[u16]` slices
+/// of UTF-16 code units.
+///
+/// **Note:** Dropping the decoder signals the end of the input:
+/// If the last input chunk ended with a lead surrogate,
+/// this is an error and a replacement character is emitted.
+/// Use `std::mem::forget` to inhibit this behavior.
+#[derive(Clone)]
+pub struct Utf16LossyDecoder<F: FnMut(&str)> {
+    push_str: F,
+    lead_surrogate: Option<u16>,
+}
+
+impl<F: FnMut(&str)> Utf16LossyDecoder<F> {
+    /// Create a new decoder from a callback.
+    #[inline]
+    pub fn new(push_str: F) -> Self {
+        Utf16LossyDecoder {
+            push_str,
+            lead_surrogate: None,
+        }
+    }
+
+    /// Feed one chunk of input into the decoder.
+    ///
+    /// The input is decoded lossily
+    /// and the callback called zero or more times with `&str` string slices.
+    ///
+    /// If a surrogate pair was split between the end of the previous chunk
+    /// and the start of this one, it will be correctly pieced back together.
+    pub fn feed(&mut self, input: &[u16]) {
+        let mut buffer = [0_u8; 256];
+        let mut len = 0;
+        for &code_unit in input {
+            // Leave room for a replacement character and one more code point
+            if buffer.len() - len < 3 + 4 {
+                (self.push_str)(unsafe { str::from_utf8_unchecked(&buffer[..len]) });
+                len = 0;
+            }
+            let lead_surrogate = self.lead_surrogate.take();
+            let c = match (lead_surrogate, code_unit) {
+                (Some(lead), 0xDC00..=0xDFFF) => {
+                    let code_point = 0x10000 + ((lead as u32 - 0xD800) << 10)
+                        + (code_unit as u32 - 0xDC00);
+                    char::from_u32(code_point).unwrap()
+                }
+                _ => {
+                    if lead_surrogate.is_some() {
+                        len += REPLACEMENT_CHAR.encode_utf8(&mut buffer[len..]).len();
+                    }
+                    match code_unit {
+                        0xD800..=0xDBFF => {
+                            self.lead_surrogate = Some(code_unit);
+                            continue
+                        }
+                        0xDC00..=0xDFFF => REPLACEMENT_CHAR,
+                        _ => char::from_u32(code_unit as u32).unwrap(),
+                    }
+                }
+            };
+            len += c.encode_utf8(&mut buffer[len..]).len();
+        }
+        if len > 0 {
+            (self.push_str)(unsafe { str::from_utf8_unchecked(&buffer[..len]) })
+        }
+    }
+}
+
+impl<F: FnMut(&str)> Drop for Utf16LossyDecoder<F> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.lead_surrogate.is_some() {
+            (self.push_str)(REPLACEMENT_CHARACTER)
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::io;
+use lossy::feed_lossy;
+use super::*;
+
+/// A `std::io::Write` sink that decodes its input as UTF-8, lossily,
+/// and pushes `&str` slices into a callback.
+///
+/// This is `LossyDecoder` reachable through the `Write` trait:
+/// a code point split across `write` calls is pieced back together.
+/// Writing never fails.
+///
+/// **Note:** `finish` and dropping the sink signal the end of the input:
+/// if the last write ended with an incomplete byte sequence for a code point,
+/// this is an error and a replacement character is emitted.
+/// `flush` does not: callers such as `BufWriter` flush in the middle of the input.
+pub struct WriteDecoder<F: FnMut(&str)> {
+    push_str: F,
+    incomplete: Incomplete,
+}
+
+impl<F: FnMut(&str)> WriteDecoder<F> {
+    /// Create a new sink from a callback.
+    #[inline]
+    pub fn new(push_str: F) -> Self {
+        WriteDecoder {
+            push_str,
+            incomplete: Incomplete::empty(),
+        }
+    }
+
+    /// Whether the sink holds the start of a code point from previous input,
+    /// waiting for more input to complete it.
+    /// If so, `finish` or dropping the sink emits a replacement character.
+    #[inline]
+    pub fn has_incomplete(&self) -> bool {
+        !self.incomplete.is_empty()
+    }
+
+    /// Signal the end of the input.
+    /// A buffered incomplete sequence is replaced with a replacement character.
+    pub fn finish(mut self) {
+        self.flush_incomplete()
+    }
+
+    fn flush_incomplete(&mut self) {
+        if !self.incomplete.is_empty() {
+            (self.push_str)(REPLACEMENT_CHARACTER);
+            self.incomplete = Incomplete::empty()
+        }
+    }
+}
+
+impl<F: FnMut(&str)> io::Write for WriteDecoder<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        feed_lossy(&mut self.incomplete, buf, ReplacementPolicy::MaximalSubpart,
+                   &mut self.push_str);
+        Ok(buf.len())
+    }
+
+    /// There is no buffered output to flush: decoded text is pushed as soon as it is complete.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&str)> Drop for WriteDecoder<F> {
+    #[inline]
+    fn drop(&mut self) {
+        self.flush_incomplete()
+    }
+}
+
+/// Decode `input` lossily, replacing errors with U+FFFD,
+/// and write the UTF-8 bytes of the result to `out` with `write_all`.
+///
+/// This cleans up bytes on their way to a file or socket without building a `String`.
+/// Writing stops at the first I/O error, which is returned.
+///
+/// ```rust
+/// let mut out = Vec::new();
+/// utf8::decode_lossy_to_writer(b"caf\xC3\xA9 \xFF", &mut out).unwrap();
+/// assert_eq!(out, "café \u{FFFD}".as_bytes());
+/// ```
+pub fn decode_lossy_to_writer<W: io::Write + ?Sized>(input: &[u8], out: &mut W)
+                                                    -> io::Result<()> {
+    let mut result = Ok(());
+    push_lossy(decode(input), |s| {
+        if result.is_ok() {
+            result = out.write_all(s.as_bytes())
+        }
+    });
+    result
+}
@@ -0,0 +1,134 @@
+use super::*;
+
+/// A slice of well-formed [WTF-8](https://simonsapin.github.io/wtf-8/) bytes.
+///
+/// WTF-8 is a superset of UTF-8 that can also represent unpaired surrogate code points
+/// (U+D800 to U+DFFF, as found in potentially ill-formed UTF-16),
+/// encoded as three-byte sequences the same way as other code points in that range.
+/// Since these are not Unicode scalar values, WTF-8 bytes are not necessarily a valid `&str`.
+/// A surrogate pair must still be encoded as a single four-byte sequence.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Wtf8<'a>(&'a [u8]);
+
+impl<'a> Wtf8<'a> {
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    /// Return the same bytes as a string slice if they contain no surrogate, `None` otherwise.
+    pub fn to_str(&self) -> Option<&'a str> {
+        str::from_utf8(self.0).ok()
+    }
+
+    /// Iterate over the code points, which can be surrogates.
+    pub fn code_points(&self) -> Wtf8CodePoints<'a> {
+        Wtf8CodePoints { bytes: self.0 }
+    }
+}
+
+/// An iterator over the code points of `Wtf8` bytes, returned by `Wtf8::code_points`.
+#[derive(Debug, Clone)]
+pub struct Wtf8CodePoints<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for Wtf8CodePoints<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        let first = *self.bytes.first()? as u32;
+        let (len, mut code_point) = match first {
+            0x00..=0x7F => (1, first),
+            0xC0..=0xDF => (2, first & 0x1F),
+            0xE0..=0xEF => (3, first & 0x0F),
+            _ => (4, first & 0x07),
+        };
+        for &byte in &self.bytes[1..len] {
+            code_point = (code_point << 6) | (byte as u32 & 0x3F);
+        }
+        self.bytes = &self.bytes[len..];
+        Some(code_point)
+    }
+}
+
+/// Like `DecodeError`, but for WTF-8.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Wtf8DecodeError<'a> {
+    /// In lossy decoding insert `valid_prefix`, then `"\u{FFFD}"`,
+    /// then call `decode_wtf8()` again with `remaining_input`.
+    Invalid {
+        valid_prefix: Wtf8<'a>,
+        invalid_sequence: &'a [u8],
+        kind: Utf8ErrorKind,
+        remaining_input: &'a [u8],
+    },
+
+    /// Call `decode_wtf8()` again with `incomplete_suffix` followed by more input when available.
+    /// If no more input is available, this is an invalid byte sequence.
+    Incomplete {
+        valid_prefix: Wtf8<'a>,
+        incomplete_suffix: &'a [u8],
+    },
+}
+
+/// Like `decode`, but accept unpaired surrogates as in WTF-8.
+///
+/// Unlike `decode`, the result is not necessarily valid UTF-8. See `Wtf8`.
+pub fn decode_wtf8(input: &[u8]) -> Result<Wtf8<'_>, Wtf8DecodeError<'_>> {
+    let mut valid_up_to = 0;
+    // Whether `input[..valid_up_to]` ends with a lead surrogate
+    let mut after_lead_surrogate = false;
+    loop {
+        match decode(&input[valid_up_to..]) {
+            Ok(_) => return Ok(Wtf8(input)),
+            Err(DecodeError::Incomplete { valid_prefix, .. }) => {
+                let (valid, incomplete) = input.split_at(valid_up_to + valid_prefix.len());
+                return Err(Wtf8DecodeError::Incomplete {
+                    valid_prefix: Wtf8(valid),
+                    incomplete_suffix: incomplete,
+                })
+            }
+            Err(DecodeError::Invalid { valid_prefix, invalid_sequence, kind, remaining_input }) => {
+                let sequence_start = valid_up_to + valid_prefix.len();
+                let sequence = &input[sequence_start..];
+                let valid_prefix = Wtf8(&input[..sequence_start]);
+                if kind != Utf8ErrorKind::Surrogate {
+                    return Err(Wtf8DecodeError::Invalid {
+                        valid_prefix, invalid_sequence, kind, remaining_input,
+                    })
+                }
+                if sequence_start > valid_up_to {
+                    after_lead_surrogate = false
+                }
+                // `sequence` starts with 0xED and a byte in 0xA0..=0xBF
+                let is_lead_surrogate = sequence[1] < 0xB0;
+                match sequence.get(2) {
+                    None => {
+                        return Err(Wtf8DecodeError::Incomplete {
+                            valid_prefix,
+                            incomplete_suffix: sequence,
+                        })
+                    }
+                    Some(&(0x80..=0xBF)) => {
+                        if after_lead_surrogate && !is_lead_surrogate {
+                            // A surrogate pair must be encoded as a supplementary code point
+                            return Err(Wtf8DecodeError::Invalid {
+                                valid_prefix, invalid_sequence, kind, remaining_input,
+                            })
+                        }
+                        after_lead_surrogate = is_lead_surrogate;
+                        valid_up_to = sequence_start + 3;
+                    }
+                    Some(_) => {
+                        return Err(Wtf8DecodeError::Invalid {
+                            valid_prefix,
+                            invalid_sequence: &sequence[..2],
+                            kind: Utf8ErrorKind::InvalidContinuation,
+                            remaining_input: &sequence[2..],
+                        })
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Check that decoders do not allocate, with a global allocator that counts allocations.
+//! This is a separate test binary so that the allocator does not affect other tests.
+
+extern crate utf8;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use utf8::*;
+
+#[path = "shared/data.rs"]
+mod data;
+
+use data::DECODED_LOSSY;
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Return how many allocations `f` made on this thread.
+fn allocations<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn test_decoders_do_not_allocate() {
+    assert_eq!(allocations(|| drop(Vec::<u8>::with_capacity(1))), 1);
+    for &(input, _expected) in DECODED_LOSSY {
+        let count = allocations(|| {
+            let mut len = 0;
+            {
+                let mut decoder = LossyDecoder::new(|s| len += s.len());
+                for byte in input.chunks(1) {
+                    decoder.feed(byte);
+                }
+                decoder.feed(input);
+            }
+            {
+                let mut decoder = CharDecoder::new(|c| len += c.len_utf8());
+                input.chunks(2).for_each(|chunk| decoder.feed(chunk));
+            }
+            {
+                let mut decoder = Utf8ToUtf16::new(|units| len += units.len());
+                input.chunks(3).for_each(|chunk| decoder.feed(chunk));
+            }
+            let mut decoder = IncrementalDecoder::new();
+            for chunk in input.chunks(1) {
+                len += decoder.feed(chunk).count();
+            }
+            decoder.finish();
+            len += Decoder::builder().allow_surrogates(true).allow_overlong(true).build()
+                .code_points(input).count();
+            len += decode_pieces(input).count() + char_indices(input).count();
+            let mut out = [0; 64];
+            len += decode_lossy_to_slice(input, &mut out).1;
+            assert!(len > 0 || input.is_empty());
+        });
+        assert_eq!(count, 0, "{:?}", input);
+    }
+}
@@ -1,14 +1,36 @@
 extern crate utf8;
+#[cfg(feature = "bytes")]
+extern crate bytes;
+extern crate proptest;
+#[cfg(feature = "futures")]
+extern crate futures_core;
+#[cfg(feature = "futures")]
+extern crate futures_io;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "tokio")]
+extern crate tokio;
 
 use std::borrow::Cow;
 use std::collections::VecDeque;
+use proptest::prelude::*;
+use proptest::sample::Index;
 use std::io;
 use utf8::*;
 
+#[path = "shared/data.rs"]
+mod data;
+
+#[path = "shared/string_from_utf8_lossy.rs"]
+mod string_from_utf8_lossy;
+
+use data::DECODED_LOSSY;
+use string_from_utf8_lossy::string_from_utf8_lossy;
+
 /// A re-implementation of std::str::from_utf8
 pub fn str_from_utf8(input: &[u8]) -> Result<&str, usize> {
     match decode(input) {
-        Ok(s) => return Ok(s),
+        Ok(s) => Ok(s),
         Err(DecodeError::Invalid { valid_prefix, .. }) |
         Err(DecodeError::Incomplete { valid_prefix, .. }) => Err(valid_prefix.len()),
     }
@@ -68,50 +90,6 @@ fn test_is_utf8() {
     assert!(str_from_utf8(&[0xF4, 0x8F, 0xBF, 0xBF]).is_ok());
 }
 
-/// A re-implementation of String::from_utf8_lossy
-pub fn string_from_utf8_lossy(input: &[u8]) -> Cow<str> {
-    let mut result = decode(input);
-    if let Ok(s) = result {
-        return s.into()
-    }
-    let mut string = String::with_capacity(input.len() + REPLACEMENT_CHARACTER.len());
-    loop {
-        match result {
-            Ok(s) => {
-                string.push_str(s);
-                return string.into()
-            }
-            Err(DecodeError::Incomplete { valid_prefix, .. }) => {
-                string.push_str(valid_prefix);
-                string.push_str(REPLACEMENT_CHARACTER);
-                return string.into()
-            }
-            Err(DecodeError::Invalid { valid_prefix, remaining_input, .. }) => {
-                string.push_str(valid_prefix);
-                string.push_str(REPLACEMENT_CHARACTER);
-                result = decode(remaining_input);
-            }
-        }
-    }
-}
-
-pub const DECODED_LOSSY: &'static [(&'static [u8], &'static str)] = &[
-    (b"hello", "hello"),
-    (b"\xe0\xb8\xa8\xe0\xb9\x84\xe0\xb8\x97\xe0\xb8\xa2\xe4\xb8\xad\xe5\x8d\x8e", "ศไทย中华"),
-    (b"Vi\xe1\xbb\x87t Nam", "Việt Nam"),
-    (b"Hello\xC2 There\xFF ", "Hello\u{FFFD} There\u{FFFD} "),
-    (b"Hello\xC0\x80 There", "Hello\u{FFFD}\u{FFFD} There"),
-    (b"\xE6\x83 Goodbye", "\u{FFFD} Goodbye"),
-    (b"\xF5foo\xF5\x80bar", "\u{FFFD}foo\u{FFFD}\u{FFFD}bar"),
-    (b"\xF5foo\xF5\xC2", "\u{FFFD}foo\u{FFFD}\u{FFFD}"),
-    (b"\xF1foo\xF1\x80bar\xF1\x80\x80baz", "\u{FFFD}foo\u{FFFD}bar\u{FFFD}baz"),
-    (b"\xF4foo\xF4\x80bar\xF4\xBFbaz", "\u{FFFD}foo\u{FFFD}bar\u{FFFD}\u{FFFD}baz"),
-    (b"\xF0\x80\x80\x80foo\xF0\x90\x80\x80bar", "\u{FFFD}\u{FFFD}\u{FFFD}\u{FFFD}foo\u{10000}bar"),
-    (b"\xF0\x90\x80foo", "\u{FFFD}foo"),
-    // surrogates
-    (b"\xED\xA0\x80foo\xED\xBF\xBFbar", "\u{FFFD}\u{FFFD}\u{FFFD}foo\u{FFFD}\u{FFFD}\u{FFFD}bar"),
-];
-
 #[test]
 fn test_string_from_utf8_lossy() {
     for &(input, expected) in DECODED_LOSSY {
@@ -119,7 +97,7 @@ fn test_string_from_utf8_lossy() {
     }
 }
 
-pub fn all_partitions<'a, F>(input: &'a [u8], f: F)
+pub fn all_partitions<F>(input: &[u8], f: F)
     where F: Fn(&[&[u8]])
 {
 
@@ -148,7 +126,7 @@ fn test_incremental_decoder() {
             let mut string = String::new();
             {
                 let mut decoder = LossyDecoder::new(|s| string.push_str(s));
-                for &chunk in &*chunks {
+                for &chunk in chunks {
                     decoder.feed(chunk);
                 }
             }
@@ -195,3 +173,1983 @@ impl<'a> io::BufRead for Chunks<'a> {
     }
 
 }
+
+/// A `Utf8Source` that is not `BufRead`: a ring buffer of `capacity` bytes,
+/// topped up on every `fill`, whose contents are not always contiguous.
+struct Ring<'a> {
+    input: &'a [u8],
+    ring: VecDeque<u8>,
+    capacity: usize,
+}
+
+impl<'a> Utf8Source for Ring<'a> {
+    fn fill(&mut self) -> io::Result<&[u8]> {
+        while self.ring.len() < self.capacity && !self.input.is_empty() {
+            self.ring.push_back(self.input[0]);
+            self.input = &self.input[1..];
+        }
+        Ok(self.ring.as_slices().0)
+    }
+
+    fn consume_bytes(&mut self, n: usize) {
+        self.ring.drain(..n);
+    }
+}
+
+#[test]
+fn test_bufread_decoder_custom_source() {
+    for &(input, expected) in DECODED_LOSSY {
+        for capacity in 1..6 {
+            let source = Ring { input, ring: VecDeque::with_capacity(capacity), capacity };
+            assert_eq!(BufReadDecoder::read_to_string_lossy(source).unwrap(), expected);
+        }
+    }
+}
+
+#[test]
+fn test_bufread_decoder_from_read() {
+    struct Unbuffered<'a>(&'a [u8]);
+    impl<'a> io::Read for Unbuffered<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    for &(input, expected) in DECODED_LOSSY {
+        let decoder = BufReadDecoder::from_read(Unbuffered(input));
+        assert_eq!(decoder.into_inner().capacity(), io::BufReader::new(&b""[..]).capacity());
+        for capacity in 1..5 {
+            let mut decoder = BufReadDecoder::from_read_with_capacity(Unbuffered(input), capacity);
+            let mut string = String::new();
+            while let Some(result) = decoder.next_strict() {
+                match result {
+                    Ok(chunk) => {
+                        assert!(chunk.len() <= capacity.max(4));
+                        string.push_str(chunk)
+                    }
+                    Err(error) => string.push_str(error.lossy().unwrap()),
+                }
+            }
+            assert_eq!(string, expected);
+        }
+    }
+}
+
+#[test]
+fn test_bufread_decoder_read_line_lossy() {
+    let inputs: &[&[u8]] = &[
+        b"a\nb", b"\xC3\xA9\nxy\n", b"\xC3\n\xA9\n", b"\n\n", b"ab\xFF\ncd\r\n", b"\xF0\x9F\x92\xA9\n\n",
+    ];
+    let mut inputs = inputs.to_vec();
+    inputs.extend(DECODED_LOSSY.iter().map(|&(input, _)| input));
+    for input in inputs {
+        let expected = String::from_utf8_lossy(input);
+        let expected: Vec<&str> = expected.split_inclusive('\n').collect();
+        all_partitions(input, |chunks| {
+            let mut decoder = BufReadDecoder::new(Chunks(chunks.to_vec().into()));
+            let mut lines = Vec::new();
+            loop {
+                let mut line = String::new();
+                let len = decoder.read_line_lossy(&mut line).unwrap();
+                assert_eq!(len, line.len());
+                if len == 0 {
+                    break
+                }
+                lines.push(line);
+            }
+            assert_eq!(lines, expected, "{:?}", chunks);
+            assert_eq!(decoder.stream_offset(), input.len());
+        });
+    }
+
+    let mut decoder = BufReadDecoder::new(Chunks(vec![&b"ab\ncd"[..], b"\nef"].into()));
+    let mut line = String::from("> ");
+    assert_eq!(decoder.read_line_lossy(&mut line).unwrap(), 3);
+    assert_eq!(line, "> ab\n");
+    assert_eq!(decoder.next_lossy().unwrap().unwrap(), "cd");
+    assert_eq!(decoder.next_lossy().unwrap().unwrap(), "\nef");
+}
+
+#[test]
+fn test_bufread_decoder_into_inner() {
+    let mut decoder = BufReadDecoder::new(&b"abc\xFFdef"[..]);
+    assert_eq!(decoder.next_strict().unwrap().unwrap(), "abc");
+    assert_eq!(*decoder.get_ref(), &b"abc\xFFdef"[..]);
+    assert_eq!(*decoder.get_mut(), &b"\xFFdef"[..]);
+    assert_eq!(decoder.into_inner(), &b"\xFFdef"[..]);
+}
+
+#[test]
+fn test_bufread_decoder_read_to_string_strict() {
+    all_partitions(b"ab\xF0\x9F\x92\xA9cd\xFFef\xC3", |chunks| {
+        let mut decoder = BufReadDecoder::new(Chunks(chunks.to_vec().into()));
+        let mut string = String::new();
+        match decoder.read_to_string_strict(&mut string) {
+            Err(BufReadDecoderError::InvalidByteSequence { invalid_sequence, byte_offset,
+                                                           incomplete }) => {
+                assert_eq!(invalid_sequence, b"\xFF");
+                assert_eq!(byte_offset, 8);
+                assert!(!incomplete);
+            }
+            _ => panic!("expected an invalid byte sequence"),
+        }
+        assert_eq!(string, "ab\u{1F4A9}cd");
+        match decoder.read_to_string_strict(&mut string) {
+            Err(BufReadDecoderError::InvalidByteSequence { invalid_sequence, byte_offset,
+                                                           incomplete }) => {
+                assert_eq!(invalid_sequence, b"\xC3");
+                assert_eq!(byte_offset, 11);
+                assert!(incomplete);
+            }
+            _ => panic!("expected an invalid byte sequence"),
+        }
+        assert_eq!(string, "ab\u{1F4A9}cdef");
+        assert_eq!(decoder.read_to_string_strict(&mut string).unwrap(), 0);
+        assert_eq!(decoder.stream_offset(), 12);
+    });
+
+    all_partitions(b"\xE2\x82A", |chunks| {
+        let mut decoder = BufReadDecoder::new(Chunks(chunks.to_vec().into()));
+        match decoder.next_strict() {
+            Some(Err(error @ BufReadDecoderError::InvalidByteSequence { incomplete: false, .. })) => {
+                assert_eq!(error.to_string(), "invalid byte sequence [e2, 82] at byte offset 0")
+            }
+            _ => panic!("expected an invalid byte sequence"),
+        }
+    });
+}
+
+#[test]
+fn test_error_kind() {
+    fn kind(input: &[u8]) -> Utf8ErrorKind {
+        match decode(input) {
+            Err(DecodeError::Invalid { kind, .. }) => kind,
+            _ => panic!("expected an invalid byte sequence"),
+        }
+    }
+    assert_eq!(kind(b"\xFF"), Utf8ErrorKind::InvalidStartByte);
+    assert_eq!(kind(b"\xF8\x88\x80\x80\x80"), Utf8ErrorKind::InvalidStartByte);
+    assert_eq!(kind(b"a\x80"), Utf8ErrorKind::UnexpectedContinuation);
+    assert_eq!(kind(b"\xC0\x80"), Utf8ErrorKind::Overlong);
+    assert_eq!(kind(b"\xC1\xBF"), Utf8ErrorKind::Overlong);
+    assert_eq!(kind(b"\xE0\x80\xAF"), Utf8ErrorKind::Overlong);
+    assert_eq!(kind(b"\xF0\x82\x82\xAC"), Utf8ErrorKind::Overlong);
+    assert_eq!(kind(b"\xED\xA0\x80"), Utf8ErrorKind::Surrogate);
+    assert_eq!(kind(b"\xF4\x90\x80\x80"), Utf8ErrorKind::CodepointTooLarge);
+    assert_eq!(kind(b"\xF5\x80\x80\x80"), Utf8ErrorKind::CodepointTooLarge);
+    assert_eq!(kind(b"\xC2A"), Utf8ErrorKind::InvalidContinuation);
+    assert_eq!(kind(b"\xE0\xA0A"), Utf8ErrorKind::InvalidContinuation);
+    assert_eq!(kind(b"\xF0\x90\x80\xC0"), Utf8ErrorKind::InvalidContinuation);
+}
+
+#[test]
+fn test_error_display() {
+    assert_eq!(
+        decode(b"ab\xED\xA0\x80cd").unwrap_err().to_string(),
+        "found invalid byte sequence [ed] (encoded surrogate) after 2 valid bytes, \
+         followed by 4 more unprocessed bytes"
+    );
+    let mut decoder = BufReadDecoder::new(&b"\xF5"[..]);
+    let error: Box<dyn std::error::Error> = Box::new(decoder.next_strict().unwrap().unwrap_err());
+    assert_eq!(error.to_string(), "invalid byte sequence [f5] at byte offset 0");
+    assert_eq!(decode(b"a\xE2\x82").unwrap_err().to_string(),
+               "found incomplete byte sequence [e2, 82] after 1 bytes");
+}
+
+#[test]
+fn test_error_debug() {
+    assert_eq!(format!("{:?}", decode(b"ab\xFFcd").unwrap_err()),
+               "Invalid { valid_prefix_len: 2, invalid_sequence: [ff], kind: InvalidStartByte, \
+                remaining_input_len: 2 }");
+    assert_eq!(format!("{:?}", decode(b"a\xE2\x82").unwrap_err()),
+               "Incomplete { valid_prefix_len: 1, incomplete_suffix: Incomplete([e2, 82]) }");
+    assert_eq!(format!("{:?}", decode(b"a\xE2\x82(").unwrap_err().into_owned()),
+               "OwnedDecodeError { valid_up_to: 1, invalid_sequence: [e2, 82], \
+                kind: InvalidContinuation }");
+    assert_eq!(format!("{:?}", Incomplete { buffer: *b"\xC3\xA9ab", buffer_len: 1 }),
+               "Incomplete([c3])");
+    assert_eq!(format!("{:?}", Incomplete { buffer: [0xF0; 4], buffer_len: 9 }),
+               "Incomplete { buffer: [f0, f0, f0, f0], buffer_len: 9 }");
+}
+
+#[test]
+fn test_decode_long_inputs() {
+    // Longer than any word-at-a-time block, with interesting bytes at every position.
+    for &insert in &[&b"\xFF"[..], b"\xC3\xA9", b"\xE2\x82", b"\xF0\x9F\x92\xA9", b"\x80"] {
+        for position in 0..40 {
+            let mut input = vec![b'a'; 40];
+            input.splice(position..position, insert.iter().cloned());
+            for end in position..input.len() + 1 {
+                let input = &input[..end];
+                let expected = std::str::from_utf8(input).map_err(|e| e.valid_up_to());
+                assert_eq!(str_from_utf8(input), expected);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_encode_char() {
+    let mut buffer = [0; 4];
+    for &c in &['\0', 'a', '\u{7F}', '\u{80}', 'é', '\u{7FF}', '\u{800}', '€', '\u{D7FF}',
+                '\u{E000}', '\u{FFFD}', '\u{FFFF}', '\u{10000}', '\u{1F4A9}', '\u{10FFFF}'] {
+        assert_eq!(encode_char(c, &mut buffer), c.encode_utf8(&mut [0; 4]));
+        assert_eq!(char_len_utf8(c), c.len_utf8());
+    }
+}
+
+#[test]
+fn test_decode_wtf8() {
+    fn code_points(input: &[u8]) -> Vec<u32> {
+        decode_wtf8(input).unwrap().code_points().collect()
+    }
+    assert_eq!(code_points(b"a\xED\xA0\xBDb"), [0x61, 0xD83D, 0x62]);
+    assert_eq!(code_points(b"\xED\xB2\xA9"), [0xDCA9]);
+    assert_eq!(code_points(b"\xED\xB2\xA9\xED\xA0\xBD"), [0xDCA9, 0xD83D]);
+    assert_eq!(code_points(b"\xED\xA0\xBDa\xED\xB2\xA9"), [0xD83D, 0x61, 0xDCA9]);
+    assert_eq!(code_points("é\u{1F4A9}".as_bytes()), [0xE9, 0x1F4A9]);
+    assert_eq!(decode_wtf8(b"abc").unwrap().to_str(), Some("abc"));
+    assert_eq!(decode_wtf8(b"\xED\xA0\xBD").unwrap().to_str(), None);
+
+    // The standard decoder still rejects surrogates
+    assert!(decode(b"\xED\xA0\xBD").is_err());
+
+    // A surrogate pair must use the four-byte form
+    match decode_wtf8(b"\xED\xA0\xBD\xED\xB2\xA9") {
+        Err(Wtf8DecodeError::Invalid { valid_prefix, invalid_sequence, kind, remaining_input }) => {
+            assert_eq!(valid_prefix.as_bytes(), b"\xED\xA0\xBD");
+            assert_eq!(invalid_sequence, b"\xED");
+            assert_eq!(kind, Utf8ErrorKind::Surrogate);
+            assert_eq!(remaining_input, b"\xB2\xA9");
+        }
+        _ => panic!("expected an invalid byte sequence"),
+    }
+    match decode_wtf8(b"\xED\xA0a") {
+        Err(Wtf8DecodeError::Invalid { invalid_sequence, remaining_input, .. }) => {
+            assert_eq!(invalid_sequence, b"\xED\xA0");
+            assert_eq!(remaining_input, b"a");
+        }
+        _ => panic!("expected an invalid byte sequence"),
+    }
+    for &input in &[&b"a\xED"[..], b"a\xED\xA0"] {
+        match decode_wtf8(input) {
+            Err(Wtf8DecodeError::Incomplete { valid_prefix, incomplete_suffix }) => {
+                assert_eq!(valid_prefix.as_bytes(), b"a");
+                assert_eq!(incomplete_suffix, &input[1..]);
+            }
+            _ => panic!("expected an incomplete byte sequence"),
+        }
+    }
+}
+
+#[test]
+fn test_modified_utf8() {
+    use utf8::modified_utf8;
+
+    let pairs: &[(&str, &[u8])] = &[
+        ("", b""),
+        ("abc", b"abc"),
+        ("\0", b"\xC0\x80"),
+        ("a\0b\0", b"a\xC0\x80b\xC0\x80"),
+        ("é€", b"\xC3\xA9\xE2\x82\xAC"),
+        ("\u{1F4A9}", b"\xED\xA0\xBD\xED\xB2\xA9"),
+        ("a\u{10000}\0\u{10FFFF}z", b"a\xED\xA0\x80\xED\xB0\x80\xC0\x80\xED\xAF\xBF\xED\xBF\xBFz"),
+    ];
+    for &(string, bytes) in pairs {
+        assert_eq!(modified_utf8::encode(string), bytes);
+        assert_eq!(modified_utf8::decode(bytes).unwrap(), string);
+    }
+    assert!(matches!(modified_utf8::decode("é€".as_bytes()).unwrap(), Cow::Borrowed(_)));
+    assert!(matches!(modified_utf8::encode("é€"), Cow::Borrowed(_)));
+
+    let errors: &[(&[u8], usize)] = &[
+        (b"a\xFF", 1),
+        (b"ab\xC0", 2),
+        (b"ab\xC0\x81", 2),
+        (b"\xC0\x80\xED\xA0\xBD", 2),
+        (b"\xC0\x80\xED\xA0\xBDx", 2),
+        (b"\xED\xB2\xA9\xED\xA0\xBD", 0),
+        (b"\xF0\x9F\x92\xA9\xED\xA0\xBD\xED\xA0\xBD", 4),
+    ];
+    for &(bytes, valid_up_to) in errors {
+        assert_eq!(modified_utf8::decode(bytes).unwrap_err().valid_up_to(), valid_up_to);
+    }
+}
+
+#[test]
+fn test_utf8_to_utf16() {
+    for &(input, expected) in DECODED_LOSSY {
+        let expected: Vec<u16> = expected.encode_utf16().collect();
+        all_partitions(input, |chunks| {
+            let mut utf16 = Vec::new();
+            {
+                let mut transcoder = Utf8ToUtf16::new(|s| utf16.extend_from_slice(s));
+                for &chunk in chunks {
+                    transcoder.feed(chunk);
+                }
+            }
+            assert_eq!(utf16, expected);
+        });
+    }
+
+    // Surrogate pairs are not split across buffer boundaries
+    let input = "a\u{1F4A9}".repeat(300);
+    let mut utf16 = Vec::new();
+    Utf8ToUtf16::new(|s| {
+        assert!(!(0xD800..0xDC00).contains(s.last().unwrap()));
+        utf16.extend_from_slice(s)
+    }).feed(input.as_bytes());
+    assert_eq!(utf16, input.encode_utf16().collect::<Vec<u16>>());
+}
+
+#[test]
+fn test_utf16_lossy_decoder() {
+    let cases: &[(&[u16], &str)] = &[
+        (&[0x61, 0x62], "ab"),
+        (&[0xE9, 0x20AC], "é€"),
+        (&[0xD83D, 0xDCA9], "\u{1F4A9}"),
+        (&[0x61, 0xDCA9, 0x62], "a\u{FFFD}b"),
+        (&[0x61, 0xD83D, 0x62], "a\u{FFFD}b"),
+        (&[0xD83D, 0xD83D, 0xDCA9], "\u{FFFD}\u{1F4A9}"),
+        (&[0xDCA9, 0xD83D], "\u{FFFD}\u{FFFD}"),
+        (&[0x61, 0xD83D], "a\u{FFFD}"),
+    ];
+    for &(input, expected) in cases {
+        for split in 0..input.len() + 1 {
+            let mut string = String::new();
+            {
+                let mut decoder = Utf16LossyDecoder::new(|s| string.push_str(s));
+                decoder.feed(&input[..split]);
+                decoder.feed(&input[split..]);
+            }
+            assert_eq!(string, expected);
+        }
+    }
+
+    let expected = "a\u{FFFD}\u{1F4A9}".repeat(300);
+    let input: Vec<u16> = expected.encode_utf16().collect();
+    let mut string = String::new();
+    Utf16LossyDecoder::new(|s| string.push_str(s)).feed(&input);
+    assert_eq!(string, expected);
+
+    // Each lead surrogate is replaced when the next one arrives
+    let mut string = String::new();
+    Utf16LossyDecoder::new(|s| string.push_str(s)).feed(&vec![0xD800_u16; 200]);
+    assert_eq!(string, "\u{FFFD}".repeat(200));
+}
+
+#[test]
+fn test_strip_bom() {
+    assert_eq!(strip_bom(b"\xEF\xBB\xBFabc"), b"abc");
+    assert_eq!(strip_bom(b"\xEF\xBB\xBF\xEF\xBB\xBF"), b"\xEF\xBB\xBF");
+    assert_eq!(strip_bom(b"\xEF\xBBabc"), b"\xEF\xBBabc");
+    assert_eq!(strip_bom(b"a\xEF\xBB\xBF"), b"a\xEF\xBB\xBF");
+}
+
+#[test]
+fn test_bufread_decoder_skip_bom() {
+    let cases: &[(&[u8], &str, &str)] = &[
+        (b"\xEF\xBB\xBFab", "ab", "\u{FEFF}ab"),
+        (b"\xEF\xBB\xBF", "", "\u{FEFF}"),
+        (b"\xEF\xBB\xBF\xEF\xBB\xBF", "\u{FEFF}", "\u{FEFF}\u{FEFF}"),
+        (b"a\xEF\xBB\xBF", "a\u{FEFF}", "a\u{FEFF}"),
+        (b"\xEF\xBBa", "\u{FFFD}a", "\u{FFFD}a"),
+        (b"\xEF\xBB\xBF\xFFa", "\u{FFFD}a", "\u{FEFF}\u{FFFD}a"),
+    ];
+    for &(input, skipped, kept) in cases {
+        all_partitions(input, |chunks| {
+            for &(skip, expected) in &[(true, skipped), (false, kept)] {
+                let chunks = Chunks(chunks.to_vec().into());
+                let mut decoder = BufReadDecoder::new(chunks).skip_bom(skip);
+                let mut string = String::new();
+                while let Some(result) = decoder.next_lossy() {
+                    string.push_str(result.unwrap())
+                }
+                assert_eq!(string, expected);
+                assert_eq!(decoder.stream_offset(), input.len());
+            }
+        });
+    }
+}
+
+#[test]
+fn test_utf8_char_width() {
+    const WIDTH: usize = utf8_char_width(0xE2);
+    assert_eq!(WIDTH, 3);
+    for byte in 0..=255_u8 {
+        let expected = match byte {
+            0x00..=0x7F => 1,
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => 0,
+        };
+        assert_eq!(utf8_char_width(byte), expected, "{:02X}", byte);
+    }
+    for c in ['a', 'é', '€', '\u{1F4A9}', '\u{10FFFF}'].iter() {
+        assert_eq!(utf8_char_width(c.to_string().as_bytes()[0]), c.len_utf8());
+    }
+}
+
+#[test]
+fn test_incomplete_accessors() {
+    let mut incomplete = match decode(b"ab\xF0\x9F") {
+        Err(DecodeError::Incomplete { incomplete_suffix, .. }) => incomplete_suffix,
+        _ => panic!("expected an incomplete byte sequence"),
+    };
+    assert_eq!(incomplete.buffered_bytes(), b"\xF0\x9F");
+    assert_eq!(incomplete.char_width(), 4);
+    assert_eq!(incomplete.bytes_needed(), 2);
+    assert!(incomplete.try_complete(b"\x92").is_none());
+    assert_eq!(incomplete.buffered_bytes(), b"\xF0\x9F\x92");
+    assert_eq!(incomplete.bytes_needed(), 1);
+
+    assert_eq!(Incomplete::empty().buffered_bytes(), b"");
+    assert_eq!(Incomplete::empty().char_width(), 0);
+    assert_eq!(Incomplete::empty().bytes_needed(), 0);
+}
+
+#[test]
+fn test_incomplete_finalize() {
+    let mut incomplete = Incomplete::new(b"\xF0\x9F");
+    assert!(incomplete.try_complete(b"\x92").is_none());
+    assert_eq!(incomplete.finalize(),
+               Err(InvalidSequenceBuf { bytes: b"\xF0\x9F\x92".to_vec(), incomplete: true }));
+    assert_eq!(Incomplete::empty().finalize(), Ok(()));
+}
+
+#[test]
+fn test_lossy_decoder_per_byte_policy() {
+    let cases: &[(&[u8], &str)] = &[
+        (b"Hello\xC0\x80 There", "Hello\u{FFFD}\u{FFFD} There"),
+        (b"\xE6\x83 Goodbye", "\u{FFFD}\u{FFFD} Goodbye"),
+        (b"\xF1foo\xF1\x80bar\xF1\x80\x80baz",
+         "\u{FFFD}foo\u{FFFD}\u{FFFD}bar\u{FFFD}\u{FFFD}\u{FFFD}baz"),
+        (b"\xF0\x90\x80", "\u{FFFD}\u{FFFD}\u{FFFD}"),
+        (b"a\xF0\x9F\x92\xA9", "a\u{1F4A9}"),
+    ];
+    for &(input, expected) in cases {
+        all_partitions(input, |chunks| {
+            let mut string = String::new();
+            {
+                let mut decoder = LossyDecoder::with_policy(
+                    |s| string.push_str(s), ReplacementPolicy::PerByte);
+                for &chunk in chunks {
+                    decoder.feed(chunk);
+                }
+            }
+            assert_eq!(string, expected);
+        });
+    }
+}
+
+#[test]
+fn test_lossy_decoder_skip_policy() {
+    for &(input, expected) in DECODED_LOSSY {
+        let expected = expected.replace(REPLACEMENT_CHARACTER, "");
+        all_partitions(input, |chunks| {
+            let mut string = String::new();
+            {
+                let mut decoder = LossyDecoder::with_policy(|s| {
+                    assert!(!s.is_empty());
+                    string.push_str(s)
+                }, ReplacementPolicy::Skip);
+                for &chunk in chunks {
+                    decoder.feed(chunk);
+                }
+            }
+            assert_eq!(string, expected);
+        });
+    }
+}
+
+#[test]
+fn test_decode_lossy() {
+    for &(input, expected) in DECODED_LOSSY {
+        assert_eq!(decode_lossy(input), expected);
+    }
+    assert!(matches!(decode_lossy("ศไทย中华".as_bytes()), Cow::Borrowed(_)));
+    assert!(matches!(decode_lossy(b"a\xFF"), Cow::Owned(_)));
+}
+
+#[test]
+fn test_decode_lossy_into() {
+    let mut string = String::from("start:");
+    for &(input, expected) in DECODED_LOSSY {
+        decode_lossy_into(input, &mut string);
+        assert!(string.ends_with(expected));
+    }
+    let expected: String = DECODED_LOSSY.iter().map(|&(_, expected)| expected).collect();
+    assert_eq!(string, format!("start:{}", expected));
+}
+
+#[test]
+fn test_lossy_decoder_clone() {
+    let string = std::cell::RefCell::new(String::new());
+    let mut decoder = LossyDecoder::new(|s| string.borrow_mut().push_str(s));
+    decoder.feed(b"a\xF0\x9F");
+    let mut snapshot = decoder.clone();
+    decoder.feed(b"\x92\xA9");
+    drop(decoder);
+    assert_eq!(*string.borrow(), "a\u{1F4A9}");
+    snapshot.feed(b"b");
+    drop(snapshot);
+    assert_eq!(*string.borrow(), "a\u{1F4A9}\u{FFFD}b");
+}
+
+#[test]
+fn test_decode_pieces() {
+    type Piece = Result<&'static str, InvalidSequence<'static>>;
+    fn invalid(bytes: &'static [u8]) -> Piece {
+        Err(InvalidSequence { bytes, incomplete: false })
+    }
+    fn incomplete(bytes: &'static [u8]) -> Piece {
+        Err(InvalidSequence { bytes, incomplete: true })
+    }
+    let cases: &[(&[u8], &[Piece])] = &[
+        (b"", &[]),
+        (b"hello", &[Ok("hello")]),
+        (b"\xFF", &[invalid(b"\xFF")]),
+        (b"a\xFF\xC0b", &[Ok("a"), invalid(b"\xFF"), invalid(b"\xC0"), Ok("b")]),
+        (b"ab\xF0\x9F", &[Ok("ab"), incomplete(b"\xF0\x9F")]),
+        (b"\xE2\x82", &[incomplete(b"\xE2\x82")]),
+        (b"\xF0\x90\x80foo\xF5", &[invalid(b"\xF0\x90\x80"), Ok("foo"), invalid(b"\xF5")]),
+    ];
+    for &(input, expected) in cases {
+        assert_eq!(decode_pieces(input).collect::<Vec<_>>(), expected);
+    }
+    for &(input, expected) in DECODED_LOSSY {
+        let lossy: String = decode_pieces(input)
+            .map(|piece| piece.unwrap_or(REPLACEMENT_CHARACTER))
+            .collect();
+        assert_eq!(lossy, expected);
+    }
+}
+
+#[test]
+fn test_decode_collect_errors() {
+    let (string, errors) = decode_collect_errors(b"a\xC0\x80b\xED\xA0\x80c\xF0\x9F\x92");
+    assert_eq!(string, "a\u{FFFD}\u{FFFD}b\u{FFFD}\u{FFFD}\u{FFFD}c\u{FFFD}");
+    let record = |byte_offset, bytes: &[u8], kind| ErrorRecord { byte_offset, bytes: bytes.to_vec(), kind };
+    assert_eq!(errors, [
+        record(1, b"\xC0", Utf8ErrorKind::Overlong),
+        record(2, b"\x80", Utf8ErrorKind::UnexpectedContinuation),
+        record(4, b"\xED", Utf8ErrorKind::Surrogate),
+        record(5, b"\xA0", Utf8ErrorKind::UnexpectedContinuation),
+        record(6, b"\x80", Utf8ErrorKind::UnexpectedContinuation),
+        record(8, b"\xF0\x9F\x92", Utf8ErrorKind::Truncated),
+    ]);
+    for &(input, expected) in DECODED_LOSSY {
+        let (string, errors) = decode_collect_errors(input);
+        assert_eq!(string, expected);
+        assert_eq!(errors.len(), expected.matches(REPLACEMENT_CHARACTER).count());
+    }
+}
+
+#[test]
+fn test_count_chars() {
+    for &s in &["", "hello", "ศไทย中华Việt Nam", "\u{1F4A9}\u{10000}\u{7F}\u{80}"] {
+        assert_eq!(count_chars(s), s.chars().count());
+        assert_eq!(count_chars_lossy(s.as_bytes()), s.chars().count());
+    }
+    for &(input, expected) in DECODED_LOSSY {
+        assert_eq!(count_chars_lossy(input), expected.chars().count());
+    }
+}
+
+#[cfg(feature = "futures")]
+#[test]
+fn test_async_utf8_stream() {
+    use futures_core::Stream;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+
+    /// Like `Chunks`, but not ready the first time each chunk is polled.
+    struct AsyncChunks<'a> {
+        chunks: Chunks<'a>,
+        ready: bool,
+    }
+
+    impl<'a> futures_io::AsyncRead for AsyncChunks<'a> {
+        fn poll_read(self: Pin<&mut Self>, _: &mut Context, _: &mut [u8])
+                     -> Poll<io::Result<usize>> {
+            unimplemented!()
+        }
+    }
+
+    impl<'a> futures_io::AsyncBufRead for AsyncChunks<'a> {
+        fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+            let this = self.get_mut();
+            if !this.ready {
+                this.ready = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending
+            }
+            Poll::Ready(io::BufRead::fill_buf(&mut this.chunks))
+        }
+
+        fn consume(self: Pin<&mut Self>, bytes: usize) {
+            let this = self.get_mut();
+            this.ready = false;
+            io::BufRead::consume(&mut this.chunks, bytes)
+        }
+    }
+
+    for &(input, expected) in DECODED_LOSSY {
+        all_partitions(input, |chunks| {
+            let chunks = AsyncChunks { chunks: Chunks(chunks.to_vec().into()), ready: false };
+            let mut stream = AsyncUtf8Stream::new(chunks);
+            let mut context = Context::from_waker(Waker::noop());
+            let mut string = String::new();
+            loop {
+                match Pin::new(&mut stream).poll_next(&mut context) {
+                    Poll::Pending => {}
+                    Poll::Ready(Some(chunk)) => string.push_str(&chunk.unwrap()),
+                    Poll::Ready(None) => break,
+                }
+            }
+            assert_eq!(string, expected)
+        });
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_tokio_utf8_decoder() {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, Waker};
+
+    /// Like `Chunks`, but not ready the first time each chunk is polled.
+    struct AsyncChunks<'a> {
+        chunks: Chunks<'a>,
+        ready: bool,
+    }
+
+    impl<'a> tokio::io::AsyncRead for AsyncChunks<'a> {
+        fn poll_read(self: Pin<&mut Self>, _: &mut Context, _: &mut tokio::io::ReadBuf)
+                     -> Poll<io::Result<()>> {
+            unimplemented!()
+        }
+    }
+
+    impl<'a> tokio::io::AsyncBufRead for AsyncChunks<'a> {
+        fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<&[u8]>> {
+            let this = self.get_mut();
+            if !this.ready {
+                this.ready = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending
+            }
+            Poll::Ready(io::BufRead::fill_buf(&mut this.chunks))
+        }
+
+        fn consume(self: Pin<&mut Self>, bytes: usize) {
+            let this = self.get_mut();
+            this.ready = false;
+            io::BufRead::consume(&mut this.chunks, bytes)
+        }
+    }
+
+    for &(input, expected) in DECODED_LOSSY {
+        all_partitions(input, |chunks| {
+            let chunks = AsyncChunks { chunks: Chunks(chunks.to_vec().into()), ready: false };
+            let mut decoder = TokioUtf8Decoder::new(chunks);
+            let mut context = Context::from_waker(Waker::noop());
+            let mut string = String::new();
+            loop {
+                match Pin::new(&mut decoder.next_lossy()).poll(&mut context) {
+                    Poll::Pending => {}
+                    Poll::Ready(Ok(Some(chunk))) => string.push_str(&chunk),
+                    Poll::Ready(Ok(None)) => break,
+                    Poll::Ready(Err(error)) => panic!("{:?}", error),
+                }
+            }
+            assert_eq!(string, expected)
+        });
+    }
+}
+
+#[test]
+fn test_write_decoder() {
+    use std::io::Write;
+
+    for &(input, expected) in DECODED_LOSSY {
+        all_partitions(input, |chunks| {
+            let mut string = String::new();
+            {
+                let mut decoder = WriteDecoder::new(|s| string.push_str(s));
+                for &chunk in chunks {
+                    decoder.write_all(chunk).unwrap();
+                }
+            }
+            assert_eq!(string, expected);
+        });
+    }
+
+    let mut string = String::new();
+    {
+        let mut decoder = WriteDecoder::new(|s| string.push_str(s));
+        let writer: &mut dyn Write = &mut decoder;
+        writer.write_all(b"a\xC3").unwrap();
+        writer.flush().unwrap();
+        writer.write_all(b"\xA9").unwrap();
+    }
+    assert_eq!(string, "aé");
+
+    let mut string = String::new();
+    let mut decoder = WriteDecoder::new(|s| string.push_str(s));
+    decoder.write_all(b"a\xC3").unwrap();
+    decoder.finish();
+    assert_eq!(string, "a\u{FFFD}");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_lossy_string_deserialize() {
+    use serde::de::value::{BytesDeserializer, Error, SeqDeserializer, StrDeserializer};
+    use serde::Deserialize;
+
+    for &(input, expected) in DECODED_LOSSY {
+        let deserializer = BytesDeserializer::<Error>::new(input);
+        assert_eq!(LossyString::deserialize(deserializer).unwrap().0, expected);
+        let deserializer = SeqDeserializer::<_, Error>::new(input.iter().cloned());
+        assert_eq!(LossyString::deserialize(deserializer).unwrap().0, expected);
+    }
+    let deserializer = StrDeserializer::<Error>::new("é");
+    assert_eq!(LossyString::deserialize(deserializer).unwrap(), LossyString("é".into()));
+}
+
+#[test]
+fn test_valid_prefix_len() {
+    assert_eq!(valid_prefix_len(b""), 0);
+    assert_eq!(valid_prefix_len(b"abc\xC3\xA9"), 5);
+    assert_eq!(valid_prefix_len(b"abc\xE2\x82"), 3);
+    assert_eq!(valid_prefix_len(b"abc\xFFdef"), 3);
+    assert_eq!(valid_prefix_len(b"0123456789abcdef0123\xF0\x9F\x92"), 20);
+    for &(input, _) in DECODED_LOSSY {
+        assert_eq!(valid_prefix_len(input), str_from_utf8(input).err().unwrap_or(input.len()));
+    }
+}
+
+#[test]
+fn test_latin1() {
+    assert!(matches!(decode_latin1(b"abc"), Cow::Borrowed("abc")));
+    assert!(matches!(decode_latin1(b"caf\xE9 \xFF\x80"), Cow::Owned(ref s) if s == "café ÿ\u{80}"));
+    let all_bytes: Vec<u8> = (0..=255).collect();
+    let expected: String = (0..=255u8).map(char::from).collect();
+    assert_eq!(decode_latin1(&all_bytes), expected);
+
+    all_partitions(b"a\xE9\xFFbc", |chunks| {
+        let mut string = String::new();
+        {
+            let mut decoder = Latin1Decoder::new(|s| {
+                assert!(!s.is_empty());
+                string.push_str(s)
+            });
+            for &chunk in chunks {
+                decoder.feed(chunk);
+            }
+        }
+        assert_eq!(string, "aéÿbc");
+    });
+
+    // Non-ASCII bytes are batched rather than pushed one at a time.
+    let mut pieces = Vec::new();
+    Latin1Decoder::new(|s| pieces.push(s.to_owned())).feed(&[0xE9; 300]);
+    assert_eq!(pieces.len(), 3);
+    assert_eq!(pieces.concat(), "é".repeat(300));
+
+    // ASCII runs are passed through, and each non-ASCII run is pushed at once.
+    let input = b"ab\xE9\xFFcd";
+    let mut pieces = Vec::new();
+    Latin1Decoder::new(|s| pieces.push((s.to_owned(), s.as_ptr()))).feed(input);
+    let strings: Vec<&str> = pieces.iter().map(|(s, _)| &**s).collect();
+    assert_eq!(strings, ["ab", "éÿ", "cd"]);
+    assert_eq!(pieces[0].1, input.as_ptr());
+    assert_eq!(pieces[2].1, input[4..].as_ptr());
+}
+
+#[test]
+fn test_to_ascii_escaped() {
+    assert_eq!(to_ascii_escaped("a\\b"), "a\\b");
+    assert_eq!(to_ascii_escaped("café 💩\u{7F}"), "caf\\u{e9} \\u{1f4a9}\u{7F}");
+    assert_eq!(to_ascii_escaped_lossy(b"caf\xC3\xA9\xFF"), "caf\\u{e9}\\u{fffd}");
+}
+
+#[test]
+fn test_bufread_decoder_with_min_fill() {
+    for &(input, expected) in DECODED_LOSSY {
+        all_partitions(input, |chunks| {
+            let mut decoder = BufReadDecoder::with_min_fill(Chunks(chunks.to_vec().into()), 3);
+            let mut string = String::new();
+            while let Some(result) = decoder.next_lossy() {
+                string.push_str(result.unwrap())
+            }
+            assert_eq!(string, expected)
+        });
+    }
+
+    let one_byte_at_a_time: Vec<&[u8]> = "cafés".as_bytes().chunks(1).collect();
+    let mut decoder = BufReadDecoder::with_min_fill(Chunks(one_byte_at_a_time.into()), 64);
+    assert_eq!(decoder.next_lossy().unwrap().unwrap(), "cafés");
+    assert!(decoder.next_lossy().is_none());
+}
+
+#[test]
+fn test_min_fill_polls_once_per_fill() {
+    use std::io::BufRead;
+
+    struct CountFillBuf<'a>(Chunks<'a>, usize);
+
+    impl<'a> io::Read for CountFillBuf<'a> {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            unimplemented!()
+        }
+    }
+
+    impl<'a> io::BufRead for CountFillBuf<'a> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            self.1 += 1;
+            self.0.fill_buf()
+        }
+
+        fn consume(&mut self, bytes: usize) {
+            self.0.consume(bytes)
+        }
+    }
+
+    let chunks: Vec<&[u8]> = vec![b"abcdefgh", b"ijklmnop", b"q", b"rs"];
+    let mut reader = MinFill::new(CountFillBuf(Chunks(chunks.into()), 0), 4);
+    let mut fills = Vec::new();
+    loop {
+        let len = {
+            let buf = reader.fill_buf().unwrap();
+            fills.push(buf.to_vec());
+            buf.len()
+        };
+        if len == 0 {
+            break
+        }
+        reader.consume(len);
+    }
+    let expected: &[&[u8]] = &[b"abcdefgh", b"ijklmnop", b"q", b"rs", b""];
+    assert_eq!(fills, expected);
+    // One call per fill, plus one to find EOF while accumulating "rs".
+    assert_eq!(reader.into_inner().1, 6);
+}
+
+#[test]
+fn test_bufread_decoder_next_lossy_coalesced() {
+    for &(input, expected) in DECODED_LOSSY {
+        all_partitions(input, |chunks| {
+            let mut decoder = BufReadDecoder::new(Chunks(chunks.to_vec().into()));
+            let mut string = String::new();
+            while let Some(result) = decoder.next_lossy_coalesced() {
+                let chunk = result.unwrap();
+                assert!(!chunk.is_empty());
+                string.push_str(chunk)
+            }
+            assert_eq!(string, expected)
+        });
+    }
+
+    let mut decoder = BufReadDecoder::new(&b"a\xFF\xC3\xF0\x9Fb\xFF"[..]);
+    let mut chunks = Vec::new();
+    while let Some(result) = decoder.next_lossy_coalesced() {
+        chunks.push(result.unwrap().to_owned())
+    }
+    assert_eq!(chunks, ["a", "\u{FFFD}\u{FFFD}\u{FFFD}b", "\u{FFFD}"]);
+}
+
+#[test]
+fn test_lossy_replacement_count() {
+    for &(input, expected) in DECODED_LOSSY {
+        let literal = str_from_utf8(input).map_or(0, |s| s.matches('\u{FFFD}').count());
+        assert_eq!(lossy_replacement_count(input) + literal, expected.matches('\u{FFFD}').count());
+    }
+    assert_eq!(lossy_replacement_count(b"abc"), 0);
+    assert_eq!(lossy_replacement_count(b"a\xFF\xC0\xE2\x82"), 3);
+}
+
+#[test]
+fn test_decoder_builder() {
+    let strict = Decoder::builder().build();
+    for &(input, expected) in DECODED_LOSSY {
+        let string: String = strict.code_points(input).map(|item| match item {
+            Ok(code_point) => std::char::from_u32(code_point).unwrap(),
+            Err(_) => '\u{FFFD}',
+        }).collect();
+        assert_eq!(string, expected);
+    }
+    let inputs: &[&[u8]] = &[
+        b"\xC0", b"\xC0\x10", b"\xE0\xFF\x10", b"\xF0\xFF\xFF\x10",
+        b"\xC0\x80", b"\xE0\x80\xAF", b"\xF0\x82\x82\xAC", b"\xF4\x90\x80\x80",
+        b"\xED\xA0\x80", b"\xED\x9F\xBF", b"\xF4\x8F\xBF\xBF", b"\xE0\xA0\x80",
+    ];
+    for &input in inputs {
+        assert_eq!(strict.is_valid(input), str_from_utf8(input).is_ok());
+    }
+
+    let wtf8_like = Decoder::builder().allow_surrogates(true).build();
+    let code_points: Vec<_> = wtf8_like.code_points(b"\xED\xA0\x80\xED\xBF\xBF\xC0\x80").collect();
+    assert_eq!(code_points, [
+        Ok(0xD800), Ok(0xDFFF), Err(InvalidSequence { bytes: b"\xC0", incomplete: false }),
+        Err(InvalidSequence { bytes: b"\x80", incomplete: false }),
+    ]);
+
+    let tolerant = DecoderBuilder::new().allow_overlong(true).build();
+    let code_points: Vec<_> = tolerant.code_points(b"\xC0\x80\xE0\x80\xAF\xF0\x82\x82\xAC\xED\xA0").collect();
+    assert_eq!(code_points, [
+        Ok(0), Ok(0x2F), Ok(0x20AC), Err(InvalidSequence { bytes: b"\xED", incomplete: false }),
+        Err(InvalidSequence { bytes: b"\xA0", incomplete: false }),
+    ]);
+    assert!(!tolerant.is_valid(b"\xF4\x90\x80\x80"));
+    assert_eq!(tolerant.code_points(b"a\xE0\x80").last(),
+               Some(Err(InvalidSequence { bytes: b"\xE0\x80", incomplete: true })));
+}
+
+#[test]
+fn test_feed_pieces() {
+    for &(input, expected) in DECODED_LOSSY {
+        all_partitions(input, |chunks| {
+            let mut incomplete = Incomplete::empty();
+            let mut string = String::new();
+            for &chunk in chunks {
+                for piece in feed_pieces(&mut incomplete, chunk) {
+                    match piece {
+                        DecodedPiece::InputSlice(s) => {
+                            assert!(!s.is_empty());
+                            string.push_str(s)
+                        }
+                        DecodedPiece::Stitched(c) => string.push_str(&c),
+                        DecodedPiece::Replacement => string.push_str(REPLACEMENT_CHARACTER),
+                    }
+                }
+            }
+            if !incomplete.is_empty() {
+                string.push_str(REPLACEMENT_CHARACTER)
+            }
+            assert_eq!(string, expected);
+        });
+    }
+
+    let mut incomplete = Incomplete::empty();
+    let pieces: Vec<_> = feed_pieces(&mut incomplete, "abc€".as_bytes()).collect();
+    assert_eq!(pieces, [DecodedPiece::InputSlice("abc€")]);
+    let pieces: Vec<_> = feed_pieces(&mut incomplete, b"a\xF0\x9F").collect();
+    assert_eq!(pieces, [DecodedPiece::InputSlice("a")]);
+    let pieces: Vec<_> = feed_pieces(&mut incomplete, b"\x92\xA9bc\xFF").collect();
+    assert_eq!(pieces, [DecodedPiece::Stitched('💩'.into()), DecodedPiece::InputSlice("bc"),
+                        DecodedPiece::Replacement]);
+    assert!(incomplete.is_empty());
+    match pieces[0] {
+        DecodedPiece::Stitched(s) => assert_eq!((&*s, s.len(), format!("{:?}", s)),
+                                                ("💩", 4, "\"💩\"".to_owned())),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_incremental_decoder_pull() {
+    for &(input, expected) in DECODED_LOSSY {
+        all_partitions(input, |chunks| {
+            let mut decoder = IncrementalDecoder::new();
+            let mut string = String::new();
+            for &chunk in chunks {
+                for piece in decoder.feed(chunk) {
+                    string.push_str(piece.unwrap_or(REPLACEMENT_CHARACTER))
+                }
+            }
+            if decoder.finish().is_some() {
+                string.push_str(REPLACEMENT_CHARACTER)
+            }
+            assert_eq!(string, expected);
+        });
+    }
+
+    let mut decoder = IncrementalDecoder::new();
+    assert_eq!(decoder.feed(b"a\xF0\x9F").collect::<Vec<_>>(), [Ok("a")]);
+    assert_eq!(decoder.feed(b"\x92\xA9b").collect::<Vec<_>>(), [Ok("💩"), Ok("b")]);
+    assert_eq!(decoder.feed(b"\xE2\x82").count(), 0);
+    assert_eq!(decoder.feed(b"c").collect::<Vec<_>>(),
+               [Err(InvalidSequence { bytes: b"\xE2\x82", incomplete: false }), Ok("c")]);
+    assert_eq!(decoder.feed(b"\xC3").count(), 0);
+    assert_eq!(decoder.finish(), Some(InvalidSequence { bytes: b"\xC3", incomplete: true }));
+    assert_eq!(decoder.finish(), None);
+}
+
+#[test]
+fn test_char_indices() {
+    let input = b"a\xC3\xA9\xFF\xE2\x82\xAC\xF0\x9F";
+    assert_eq!(char_indices(input).collect::<Vec<_>>(), [
+        Ok((0, 'a')), Ok((1, 'é')),
+        Err((3, InvalidSequence { bytes: b"\xFF", incomplete: false })),
+        Ok((4, '€')),
+        Err((7, InvalidSequence { bytes: b"\xF0\x9F", incomplete: true })),
+    ]);
+    assert_eq!(char_indices_lossy(input).collect::<Vec<_>>(),
+               [(0, 'a'), (1, 'é'), (3, '\u{FFFD}'), (4, '€'), (7, '\u{FFFD}')]);
+    for &(input, expected) in DECODED_LOSSY {
+        let string: String = char_indices_lossy(input).map(|(_, c)| c).collect();
+        assert_eq!(string, expected);
+    }
+}
+
+#[test]
+fn test_decode_iter() {
+    for &(input, expected) in DECODED_LOSSY {
+        let string: String = decode_iter(input.iter().cloned()).collect();
+        assert_eq!(string, expected);
+    }
+    let chained = b"caf\xC3".iter().chain(b"\xA9\xF0\x9F").cloned();
+    assert_eq!(decode_iter(chained).collect::<String>(), "café\u{FFFD}");
+}
+
+#[test]
+fn test_incomplete_try_complete_offsets() {
+    let mut incomplete = Incomplete::new(b"\xE2");
+    assert_eq!(incomplete.try_complete_offsets(b"\x82"), (1, None));
+    assert_eq!(incomplete.try_complete_offsets(b"\xACabc"), (2, Some(Ok(()))));
+    assert_eq!(incomplete.take_buffer(), "€a".as_bytes());
+    assert!(incomplete.is_empty());
+
+    let mut incomplete = Incomplete::new(b"\xF0\x9F");
+    assert_eq!(incomplete.try_complete_offsets(b"a"), (0, Some(Err(()))));
+    assert_eq!(incomplete.take_buffer(), b"\xF0\x9F");
+}
+
+#[test]
+fn test_decode_prefix() {
+    assert_eq!(decode_prefix(b"abc\xC3\xA9"), ("abcé", &b""[..]));
+    assert_eq!(decode_prefix(b"abc\xFFdef"), ("abc", &b"\xFFdef"[..]));
+    assert_eq!(decode_prefix(b"abc\xE2\x82"), ("abc", &b"\xE2\x82"[..]));
+    assert_eq!(decode_prefix(b""), ("", &b""[..]));
+}
+
+#[test]
+fn test_lossy_decoder_to_buf() {
+    let mut string = String::from(">");
+    {
+        let mut decoder = LossyDecoder::to_buf(&mut string);
+        decoder.feed(b"caf\xC3");
+        decoder.feed(b"\xA9\xF0\x9F");
+    }
+    assert_eq!(string, ">café\u{FFFD}");
+}
+
+#[test]
+fn test_incomplete_try_complete_checked() {
+    let mut incomplete = Incomplete::new(b"\xC3");
+    assert_eq!(incomplete.try_complete_checked(b"\xA9").unwrap(), Some((Ok("é"), &b""[..])));
+    assert_eq!(incomplete.try_complete_checked(b"a"), Err(IncompleteStateError::Empty));
+
+    let mut incomplete = Incomplete::new(b"\xE2");
+    assert_eq!(incomplete.try_complete_checked(b"\x82").unwrap(), None);
+
+    let mut malformed = Incomplete { buffer: *b"a\xFF\0\0", buffer_len: 2 };
+    assert_eq!(malformed.try_complete_checked(b"a"), Err(IncompleteStateError::Malformed));
+    let mut malformed = Incomplete { buffer: [0xF0; 4], buffer_len: 9 };
+    assert_eq!(malformed.try_complete_checked(b"a"), Err(IncompleteStateError::Malformed));
+    assert_eq!(IncompleteStateError::Empty.to_string(), "no incomplete code point is buffered");
+}
+
+#[test]
+fn test_decode_stats() {
+    assert_eq!(decode_stats("aé€💩b".as_bytes()), DecodeStats {
+        ascii: 2, two_byte: 1, three_byte: 1, four_byte: 1, errors: 0, incomplete_tail: false,
+    });
+    assert_eq!(decode_stats(b"a\xFF\xC3\xA9\xE2\x82"), DecodeStats {
+        ascii: 1, two_byte: 1, errors: 2, incomplete_tail: true, ..DecodeStats::default()
+    });
+    for &(input, expected) in DECODED_LOSSY {
+        let stats = decode_stats(input);
+        let total = stats.ascii + stats.two_byte + stats.three_byte + stats.four_byte + stats.errors;
+        assert_eq!(total, expected.chars().count());
+        assert_eq!(stats.errors, lossy_replacement_count(input));
+    }
+}
+
+#[test]
+fn test_decode_frame() {
+    assert_eq!(decode_frame(b"ab", true).unwrap(), "ab");
+    match decode_frame(b"ab\xE2\x82", false) {
+        Err(DecodeError::Incomplete { valid_prefix: "ab", incomplete_suffix }) => {
+            assert_eq!(incomplete_suffix.buffered_bytes(), b"\xE2\x82")
+        }
+        other => panic!("{:?}", other),
+    }
+    match decode_frame(b"ab\xE2\x82", true) {
+        Err(DecodeError::Invalid { valid_prefix: "ab", invalid_sequence: b"\xE2\x82",
+                                   kind: Utf8ErrorKind::Truncated, remaining_input: b"" }) => {}
+        other => panic!("{:?}", other),
+    }
+    match decode_frame(b"a\xFFb", true) {
+        Err(DecodeError::Invalid { kind: Utf8ErrorKind::InvalidStartByte, .. }) => {}
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn test_decode_error_eq() {
+    assert_eq!(decode(b"ab\xFFc"), Err(DecodeError::Invalid {
+        valid_prefix: "ab",
+        invalid_sequence: b"\xFF",
+        kind: Utf8ErrorKind::InvalidStartByte,
+        remaining_input: b"c",
+    }));
+    assert_eq!(decode(b"ab\xE2\x82"), Err(DecodeError::Incomplete {
+        valid_prefix: "ab",
+        incomplete_suffix: Incomplete::new(b"\xE2\x82"),
+    }));
+    let stale = Incomplete { buffer: *b"\xE2\x82\xAC!", buffer_len: 2 };
+    assert_eq!(stale, Incomplete::new(b"\xE2\x82"));
+    assert_ne!(stale, Incomplete::new(b"\xE2"));
+    let malformed = Incomplete { buffer: [0xF0; 4], buffer_len: 9 };
+    assert_eq!(malformed, malformed.clone());
+    assert_ne!(malformed, Incomplete { buffer: [0xF0; 4], buffer_len: 4 });
+}
+
+#[test]
+fn test_invalid_sequence_buf() {
+    use std::collections::BTreeMap;
+
+    let mut counts = BTreeMap::new();
+    for input in &[&b"a\xFFb\xC0"[..], b"\xFF\xE2\x82"] {
+        for piece in decode_pieces(input) {
+            if let Err(invalid_sequence) = piece {
+                *counts.entry(invalid_sequence.to_buf()).or_insert(0) += 1;
+            }
+        }
+    }
+    let counts: Vec<_> = counts.iter().map(|(k, &v)| (k.as_invalid_sequence(), v)).collect();
+    assert_eq!(counts, [
+        (InvalidSequence { bytes: b"\xC0", incomplete: false }, 1),
+        (InvalidSequence { bytes: b"\xE2\x82", incomplete: true }, 1),
+        (InvalidSequence { bytes: b"\xFF", incomplete: false }, 2),
+    ]);
+    let owned: InvalidSequenceBuf = InvalidSequence { bytes: b"\xFF", incomplete: false }.into();
+    assert_eq!(owned.bytes, b"\xFF");
+}
+
+#[test]
+fn test_invalid_sequence_len_and_hex() {
+    let invalid_sequence = InvalidSequence { bytes: b"\xF5\x80", incomplete: false };
+    assert_eq!(invalid_sequence.len(), 2);
+    assert!(!invalid_sequence.is_empty());
+    assert_eq!(invalid_sequence.to_hex(), "f5 80");
+    let empty = InvalidSequence { bytes: b"", incomplete: false };
+    assert!(empty.is_empty());
+    assert_eq!(empty.to_hex(), "");
+}
+
+#[test]
+fn test_lossy_decoder_with_error_callback() {
+    for &(input, expected) in DECODED_LOSSY {
+        all_partitions(input, |chunks| {
+            let mut string = String::new();
+            let mut errors = Vec::new();
+            {
+                let mut decoder = LossyDecoder::with_error_callback(
+                    |s| string.push_str(s),
+                    |bytes| errors.push(bytes.to_vec()),
+                );
+                for &chunk in chunks {
+                    decoder.feed(chunk);
+                }
+            }
+            assert_eq!(string, expected);
+            let expected_errors: Vec<_> = decode_pieces(input)
+                .filter_map(|piece| piece.err().map(|e| e.bytes.to_vec()))
+                .collect();
+            assert_eq!(errors, expected_errors);
+        });
+    }
+}
+
+#[test]
+fn test_decode_lossy_into_vec() {
+    let mut out = b">".to_vec();
+    for &(input, expected) in DECODED_LOSSY {
+        out.truncate(1);
+        decode_lossy_into_vec(input, &mut out);
+        assert_eq!(std::str::from_utf8(&out[1..]).unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_bufread_decoder_with_max_chunk() {
+    for max_chunk in 1..6 {
+        for &(input, expected) in DECODED_LOSSY {
+            all_partitions(input, |chunks| {
+                let chunks = Chunks(chunks.to_vec().into());
+                let mut decoder = BufReadDecoder::with_max_chunk(chunks, max_chunk);
+                let mut string = String::new();
+                while let Some(result) = decoder.next_lossy() {
+                    let chunk = result.unwrap();
+                    assert!(chunk.len() <= max_chunk || chunk.chars().count() == 1);
+                    string.push_str(chunk)
+                }
+                assert_eq!(string, expected)
+            });
+        }
+    }
+
+    let mut decoder = BufReadDecoder::with_max_chunk(&b"abcd\xC3\xA9f"[..], 5);
+    assert_eq!(decoder.next_strict().unwrap().unwrap(), "abcd");
+    assert_eq!(decoder.next_strict().unwrap().unwrap(), "éf");
+    assert_eq!(decoder.stream_offset(), 7);
+}
+
+#[test]
+fn test_const_helpers() {
+    const _: () = assert!(utf8_char_width(0xF0) == 4 && is_continuation_byte(0x9F));
+    const EURO_LEN: usize = char_len_utf8('€');
+    assert_eq!(EURO_LEN, 3);
+    for byte in 0..=255_u8 {
+        assert_eq!(is_continuation_byte(byte), (0x80..=0xBF).contains(&byte), "{:02X}", byte);
+    }
+}
+
+#[test]
+fn test_into_valid_utf8() {
+    let input = "café".as_bytes().to_vec();
+    let pointer = input.as_ptr();
+    let string = into_valid_utf8(input);
+    assert_eq!(string, "café");
+    assert_eq!(string.as_ptr(), pointer);
+
+    for &(input, expected) in DECODED_LOSSY {
+        let string = into_valid_utf8(input.to_vec());
+        assert_eq!(string, expected);
+        assert!(string.capacity() >= input.len());
+    }
+}
+
+#[test]
+fn test_first_error() {
+    assert_eq!(first_error(b"abc"), None);
+    assert_eq!(first_error(b"abc\xE2\x82"), None);
+    assert_eq!(first_error(b"ab\xE2\x82c\xFF"), Some((2, 4)));
+    assert_eq!(first_error(b"\xFF\xFF"), Some((0, 1)));
+    assert_eq!(first_error(b"0123456789abcdef0123\xF0\x9F\x92a"), Some((20, 23)));
+}
+
+#[test]
+fn test_truncate_incomplete_silently() {
+    let mut string = String::new();
+    {
+        let mut decoder = LossyDecoder::new(|s| string.push_str(s))
+            .truncate_incomplete_silently(true);
+        decoder.feed(b"ab\xFF\xF0\x9F");
+    }
+    assert_eq!(string, "ab\u{FFFD}");
+
+    all_partitions(b"ab\xFF\xF0\x9F", |chunks| {
+        let chunks = Chunks(chunks.to_vec().into());
+        let mut decoder = BufReadDecoder::new(chunks).truncate_incomplete_silently(true);
+        let mut string = String::new();
+        while let Some(result) = decoder.next_lossy() {
+            string.push_str(result.unwrap())
+        }
+        assert_eq!(string, "ab\u{FFFD}");
+        assert_eq!(decoder.stream_offset(), 3);
+    });
+}
+
+#[test]
+fn test_owned_decode_error() {
+    fn decode_local() -> Result<String, Box<dyn std::error::Error>> {
+        let buffer = b"ab\xF0\x9F\x92c".to_vec();
+        Ok(decode(&buffer).map_err(DecodeError::into_owned)?.to_owned())
+    }
+    let error = decode_local().unwrap_err();
+    assert_eq!(error.to_string(),
+               "found invalid byte sequence [f0, 9f, 92] (invalid continuation byte) \
+                after 2 valid bytes");
+
+    let error = decode(b"ab\xF0\x9F\x92c").unwrap_err().into_owned();
+    assert_eq!((error.valid_up_to(), error.invalid_sequence()), (2, &b"\xF0\x9F\x92"[..]));
+    assert_eq!(error.kind(), Utf8ErrorKind::InvalidContinuation);
+    assert!(!error.is_incomplete());
+    let error = decode(b"a\xE2\x82").unwrap_err().into_owned();
+    assert_eq!((error.valid_up_to(), error.invalid_sequence()), (1, &b"\xE2\x82"[..]));
+    assert!(error.is_incomplete());
+}
+
+#[test]
+fn test_decode_error_accessors() {
+    let error = decode(b"ab\xFFcd").unwrap_err();
+    assert_eq!((error.valid_prefix(), error.valid_up_to()), ("ab", 2));
+    assert_eq!(error.remaining_input(), b"cd");
+    let error = decode(b"a\xE2\x82").unwrap_err();
+    assert_eq!((error.valid_prefix(), error.valid_up_to()), ("a", 1));
+    assert_eq!(error.remaining_input(), b"");
+}
+
+#[test]
+fn test_decode_relaxed() {
+    assert_eq!(decode_relaxed(b"a\xC0\x80b\xE0\x81\x81\xF0\x80\x81\x81").unwrap(), "a\0bAA");
+    assert!(matches!(decode_relaxed("é€".as_bytes()).unwrap(), Cow::Borrowed("é€")));
+    assert!(decode(b"\xC0\x80").is_err());
+
+    let error = decode_relaxed(b"\xC0\x80a\xC0a").unwrap_err();
+    assert_eq!((error.valid_up_to(), error.invalid_sequence()), (3, &b"\xC0"[..]));
+    assert_eq!(error.kind(), Utf8ErrorKind::InvalidContinuation);
+    let error = decode_relaxed(b"\xF0\x8D\xA0\x80").unwrap_err();
+    assert_eq!(error.invalid_sequence(), b"\xF0\x8D\xA0\x80");
+    assert_eq!(error.kind(), Utf8ErrorKind::Surrogate);
+    let error = decode_relaxed(b"\xC0\x80\xE0\x80").unwrap_err();
+    assert_eq!((error.valid_up_to(), error.invalid_sequence()), (2, &b"\xE0\x80"[..]));
+    assert!(error.is_incomplete());
+}
+
+#[test]
+fn test_lossy_decoder_feed_str() {
+    let mut string = String::new();
+    {
+        let mut decoder = LossyDecoder::new(|s| string.push_str(s));
+        decoder.feed(b"a\xC3");
+        decoder.feed_str("\u{A9}b");
+        decoder.feed(b"\xC3");
+        decoder.feed(b"\xA9");
+        decoder.feed_str("");
+        decoder.feed_str("c");
+    }
+    assert_eq!(string, "a\u{FFFD}\u{A9}b\u{E9}c");
+}
+
+#[test]
+fn test_char_decoder() {
+    for &(input, expected) in DECODED_LOSSY {
+        all_partitions(input, |chunks| {
+            let mut chars = Vec::new();
+            {
+                let mut decoder = CharDecoder::new(|c| chars.push(c));
+                for &chunk in chunks {
+                    decoder.feed(chunk);
+                }
+            }
+            assert_eq!(chars, expected.chars().collect::<Vec<char>>());
+        });
+    }
+}
+
+/// Every way to split each code point at the edges of the 2, 3 and 4 byte ranges
+/// into consecutive chunks, complete and truncated, with and without surrounding text.
+#[test]
+fn test_split_code_points() {
+    const CODE_POINTS: &[char] = &[
+        '\u{80}', '\u{7FF}', '\u{800}', '\u{D7FF}', '\u{E000}', '\u{FFFF}',
+        '\u{10000}', '\u{10FFFF}',
+    ];
+    fn check(bytes: &[u8], expected: &str) {
+        // Bit `i` of `splits` set means a chunk boundary after byte `i`.
+        for splits in 0..1_u32 << (bytes.len() - 1) {
+            let mut chunks = Vec::new();
+            let mut start = 0;
+            for i in 0..bytes.len() {
+                if i == bytes.len() - 1 || splits & (1 << i) != 0 {
+                    chunks.push(&bytes[start..i + 1]);
+                    start = i + 1;
+                }
+            }
+            assert_eq!(decode_lossy_with_try_complete(&chunks), expected, "{:?}", chunks);
+
+            let mut string = String::new();
+            {
+                let mut decoder = LossyDecoder::new(|s| string.push_str(s));
+                for &chunk in &chunks {
+                    decoder.feed(chunk);
+                }
+            }
+            assert_eq!(string, expected, "{:?}", chunks);
+        }
+    }
+    for &c in CODE_POINTS {
+        let encoded = c.to_string().into_bytes();
+        for len in 1..encoded.len() + 1 {
+            let truncated = &encoded[..len];
+            let expected = if len == encoded.len() { c } else { '\u{FFFD}' };
+            check(truncated, &expected.to_string());
+            check(&[b"a", truncated, b"b"].concat(), &format!("a{}b", expected));
+            // A truncated sequence followed by a code point that does not continue it.
+            check(&[truncated, &encoded].concat(), &[expected, c].iter().collect::<String>());
+        }
+    }
+}
+
+#[test]
+fn test_lossy_decoder_normalize_newlines() {
+    let inputs: &[&[u8]] = &[
+        b"a\r\nb", b"a\rb\nc", b"\r\r\n\n\r", b"\r\xFF\n", b"\r\xC3\n\xA9", b"\xE2\x82\r\n",
+        "ab\r\n€\r".as_bytes(),
+    ];
+    for &input in inputs {
+        let expected = String::from_utf8_lossy(input).replace("\r\n", "\n").replace('\r', "\n");
+        all_partitions(input, |chunks| {
+            let mut string = String::new();
+            {
+                let mut decoder = LossyDecoder::new(|s| string.push_str(s))
+                    .normalize_newlines(true);
+                for &chunk in chunks {
+                    decoder.feed(chunk);
+                }
+            }
+            assert_eq!(string, expected, "{:?}", chunks);
+        });
+    }
+
+    let mut string = String::new();
+    {
+        let mut decoder = LossyDecoder::new(|s| string.push_str(s)).normalize_newlines(true);
+        assert!(decoder.feed(b"a\r"));
+        assert!(!decoder.feed(b"\n"));
+        decoder.feed_str("\nb\r\n");
+    }
+    assert_eq!(string, "a\n\nb\n");
+}
+
+#[test]
+fn test_has_incomplete() {
+    use std::io::Write;
+
+    let mut decoder = LossyDecoder::new(|_| {});
+    assert!(!decoder.has_incomplete());
+    decoder.feed(b"a\xF0\x9F");
+    assert!(decoder.has_incomplete());
+    decoder.feed(b"\x92\xA9");
+    assert!(!decoder.has_incomplete());
+
+    let mut decoder = CharDecoder::new(|_| {});
+    decoder.feed(b"\xC3");
+    assert!(decoder.has_incomplete());
+
+    let mut decoder = OwningDecoder::new();
+    decoder.feed(b"\xE2\x82");
+    assert!(decoder.has_incomplete());
+    decoder.feed(b"(");
+    assert!(!decoder.has_incomplete());
+
+    let mut decoder = WriteDecoder::new(|_| {});
+    decoder.write_all(b"\xC3").unwrap();
+    assert!(decoder.has_incomplete());
+    decoder.flush().unwrap();
+    assert!(decoder.has_incomplete());
+    decoder.write_all(b"\xA9").unwrap();
+    assert!(!decoder.has_incomplete());
+
+    let mut decoder = Utf8ToUtf16::new(|_| {});
+    decoder.feed(b"\xC3");
+    assert!(decoder.has_incomplete());
+
+    let mut decoder = IncrementalDecoder::new();
+    decoder.feed(b"ab\xC3").for_each(drop);
+    assert!(decoder.has_incomplete());
+    decoder.feed(b"\xA9").for_each(drop);
+    assert!(!decoder.has_incomplete());
+}
+
+#[test]
+fn test_owning_decoder() {
+    for &(input, expected) in DECODED_LOSSY {
+        all_partitions(input, |chunks| {
+            let mut decoder = OwningDecoder::new();
+            let mut string = String::new();
+            for &chunk in chunks {
+                decoder.feed(chunk);
+                let decoded = decoder.as_str().to_owned();
+                assert_eq!(decoder.take(), decoded);
+                assert_eq!(decoder.as_str(), "");
+                string += &decoded;
+            }
+            string += &decoder.finish();
+            assert_eq!(string, expected);
+        });
+    }
+}
+
+#[test]
+fn test_lossy_decoder_with_replacement() {
+    let config = String::from("[?]");
+    for &(input, expected) in DECODED_LOSSY {
+        let expected = expected.replace('\u{FFFD}', &config);
+        all_partitions(input, |chunks| {
+            let mut string = String::new();
+            {
+                let mut decoder = LossyDecoder::with_replacement(|s| string.push_str(s), &*config);
+                for &chunk in chunks {
+                    decoder.feed(chunk);
+                }
+            }
+            assert_eq!(string, expected);
+        });
+    }
+
+    let mut pieces = Vec::new();
+    LossyDecoder::with_replacement(|s| pieces.push(s.to_owned()), "").feed(b"a\xFFb\xC3");
+    assert_eq!(pieces, ["a", "b"]);
+}
+
+/// The same checks as the `decode_vs_std` fuzz target,
+/// on every sequence of up to 4 bytes from an alphabet of boundary values.
+#[test]
+fn test_decode_matches_std() {
+    const BYTES: &[u8] = &[
+        0x00, 0x41, 0x7F, 0x80, 0x8F, 0x90, 0x9F, 0xA0, 0xBF, 0xC0, 0xC1, 0xC2,
+        0xDF, 0xE0, 0xE1, 0xED, 0xEF, 0xF0, 0xF1, 0xF4, 0xF5, 0xFF,
+    ];
+    fn check(input: &[u8]) {
+        match (decode(input), std::str::from_utf8(input)) {
+            (Ok(s), Ok(expected)) => assert_eq!(s, expected),
+            (Err(DecodeError::Invalid { valid_prefix, invalid_sequence, .. }), Err(error)) => {
+                assert_eq!(valid_prefix.len(), error.valid_up_to());
+                assert_eq!(Some(invalid_sequence.len()), error.error_len());
+            }
+            (Err(DecodeError::Incomplete { valid_prefix, incomplete_suffix }), Err(error)) => {
+                assert_eq!(valid_prefix.len(), error.valid_up_to());
+                assert_eq!(error.error_len(), None);
+                assert_eq!(incomplete_suffix.buffered_bytes(), &input[error.valid_up_to()..]);
+            }
+            (result, expected) => panic!("{:?}: {:?} != {:?}", input, result, expected),
+        }
+        if let (Err(error), Err(expected)) = (decode(input), std::str::from_utf8(input)) {
+            assert_eq!(error.as_std_like(), (expected.valid_up_to(), expected.error_len()));
+        }
+        assert_eq!(decode_positions(input),
+                   std::str::from_utf8(input).map(str::len)
+                       .map_err(|error| (error.valid_up_to(), error.error_len())));
+        let expected = String::from_utf8_lossy(input);
+        for &capacity in &[1, 2, 3] {
+            let reader = io::BufReader::with_capacity(capacity, input);
+            assert_eq!(BufReadDecoder::read_to_string_lossy(reader).unwrap(), expected);
+        }
+    }
+    fn recurse(input: &mut Vec<u8>, check: fn(&[u8])) {
+        check(input);
+        if input.len() < 4 {
+            for &byte in BYTES {
+                input.push(byte);
+                recurse(input, check);
+                input.pop();
+            }
+        }
+    }
+    recurse(&mut Vec::new(), check);
+}
+
+#[test]
+fn test_write_lossy() {
+    for &(input, expected) in DECODED_LOSSY {
+        let mut string = String::new();
+        write_lossy(&mut string, input).unwrap();
+        assert_eq!(string, expected);
+    }
+
+    struct Fail;
+    impl std::fmt::Write for Fail {
+        fn write_str(&mut self, _: &str) -> std::fmt::Result {
+            Err(std::fmt::Error)
+        }
+    }
+    assert!(write_lossy(&mut Fail, b"a\xFFb").is_err());
+}
+
+#[test]
+fn test_lossy_lines() {
+    let inputs: &[&[u8]] = &[
+        b"", b"\n", b"a", b"a\n", b"a\r\nb", b"a\rb\r", b"\r\n\r\n", b"\n\nx\n\n",
+        b"\xC3\n\xA9", b"\xE2\x82\r\n\xFF\r",
+    ];
+    let mut inputs = inputs.to_vec();
+    inputs.extend(DECODED_LOSSY.iter().map(|&(input, _)| input));
+    for input in inputs {
+        let lines: Vec<_> = lossy_lines(input).collect();
+        let expected = String::from_utf8_lossy(input);
+        assert_eq!(lines, expected.lines().collect::<Vec<_>>(), "{:?}", input);
+        for line in &lines {
+            assert_eq!(matches!(*line, Cow::Borrowed(_)), !line.contains('\u{FFFD}'));
+        }
+    }
+}
+
+#[test]
+fn test_split_points() {
+    for &(_, expected) in DECODED_LOSSY {
+        let points: Vec<usize> = split_points(expected).collect();
+        let boundaries: Vec<usize> =
+            (0..expected.len() + 1).filter(|&i| expected.is_char_boundary(i)).collect();
+        assert_eq!(points, boundaries);
+    }
+    assert_eq!(split_points("").collect::<Vec<_>>(), [0]);
+
+    let input = "a\u{301}\u{20DD}b\u{FE20}";
+    let points: Vec<usize> = split_points(input).skip_before_combining_marks(true).collect();
+    assert_eq!(points, [0, 6, 10]);
+    let points: Vec<usize> = split_points("\u{301}a").skip_before_combining_marks(true).collect();
+    assert_eq!(points, [2, 3]);
+}
+
+#[test]
+fn test_decode_one() {
+    for &(input, expected) in DECODED_LOSSY {
+        let mut rest = input;
+        let mut string = String::new();
+        while !rest.is_empty() {
+            let len = match decode_one(rest) {
+                Ok((c, len)) => {
+                    string.push(c);
+                    len
+                }
+                Err(DecodeOneError::Invalid { len, .. }) |
+                Err(DecodeOneError::Incomplete { len }) => {
+                    string.push('\u{FFFD}');
+                    len
+                }
+                Err(DecodeOneError::Empty) => unreachable!(),
+            };
+            rest = &rest[len..];
+        }
+        assert_eq!(string, expected, "{:?}", input);
+    }
+    assert_eq!(decode_one(b"\xF0\x9F\x92\xA9a"), Ok(('\u{1F4A9}', 4)));
+    assert_eq!(decode_one(b"\xED\xA0\x80"),
+               Err(DecodeOneError::Invalid { len: 1, kind: Utf8ErrorKind::Surrogate }));
+    assert_eq!(decode_one(b"\x80"),
+               Err(DecodeOneError::Invalid { len: 1, kind: Utf8ErrorKind::UnexpectedContinuation }));
+}
+
+#[test]
+fn test_decode_lossy_with_progress() {
+    let mut inputs: Vec<&[u8]> = DECODED_LOSSY.iter().map(|&(input, _)| input).collect();
+    inputs.push(b"a\x80\x80\x80\x80\x80\xF0\x9F\x92\xA9\xF0\x9F\x92");
+    for input in inputs {
+        let expected = String::from_utf8_lossy(input);
+        for chunk in 1..8 {
+            let mut string = String::new();
+            let mut progress = Vec::new();
+            decode_lossy_with_progress(input, chunk, |s| string.push_str(s),
+                                       |done| progress.push(done));
+            assert_eq!(string, expected, "{:?} {}", input, chunk);
+            assert!(progress.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(progress.last().cloned().unwrap_or(0), input.len());
+        }
+    }
+}
+
+#[test]
+fn test_decode_lossy_to_writer() {
+    for &(input, expected) in DECODED_LOSSY {
+        let mut out = Vec::new();
+        decode_lossy_to_writer(input, &mut out).unwrap();
+        assert_eq!(out, expected.as_bytes());
+    }
+
+    // Not enough room for "ab" and the replacement character.
+    let mut buffer = [0; 4];
+    let error = decode_lossy_to_writer(b"ab\xFFcd", &mut &mut buffer[..]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::WriteZero);
+}
+
+#[test]
+fn test_is_valid_utf8() {
+    for &(input, expected) in DECODED_LOSSY {
+        assert_eq!(is_valid_utf8(input), !expected.contains('\u{FFFD}'), "{:?}", input);
+    }
+    assert!(is_valid_utf8(b""));
+    assert!(is_valid_utf8("a".repeat(40).as_bytes()));
+    let mut input = vec![b'a'; 40];
+    input.extend_from_slice(b"\xEF\xBF");
+    assert!(!is_valid_utf8(&input));
+}
+
+#[test]
+fn test_lossy_decoder_feed_all() {
+    for &(input, expected) in DECODED_LOSSY {
+        all_partitions(input, |chunks| {
+            let mut string = String::new();
+            LossyDecoder::new(|s| string.push_str(s)).feed_all(chunks.iter().map(|c| c.to_vec()));
+            assert_eq!(string, expected);
+        });
+    }
+}
+
+#[test]
+fn test_incremental_decoder_consumed() {
+    let mut decoder = IncrementalDecoder::new();
+    {
+        let mut pieces = decoder.feed(b"ab\xFFcd\xE2\x82");
+        assert_eq!(pieces.consumed(), 0);
+        assert_eq!(pieces.next(), Some(Ok("ab")));
+        assert_eq!(pieces.consumed(), 2);
+        assert!(pieces.next().unwrap().is_err());
+        assert_eq!(pieces.consumed(), 3);
+        assert_eq!(pieces.next(), Some(Ok("cd")));
+        assert_eq!(pieces.consumed(), 5);
+        assert_eq!(pieces.next(), None);
+        assert_eq!(pieces.consumed(), 7);
+    }
+    {
+        let mut pieces = decoder.feed(b"\xACxyz");
+        assert_eq!(pieces.consumed(), 1);
+        assert_eq!(pieces.next(), Some(Ok("€")));
+        // Stop early: the rest of the chunk can be fed again.
+        assert_eq!(pieces.consumed(), 1);
+    }
+    assert_eq!(decoder.feed(b"xyz").collect::<Vec<_>>(), [Ok("xyz")]);
+    let mut pieces = decoder.feed(b"\xF0\x9F");
+    assert_eq!((pieces.next(), pieces.consumed()), (None, 2));
+    let mut pieces = decoder.feed(b"\x92");
+    assert_eq!((pieces.next(), pieces.consumed()), (None, 1));
+}
+
+#[test]
+fn test_lossy_decoder_with_kind_replacement() {
+    all_partitions(b"a\xED\xA0\x80b\xC0\x80c\xE0\x80d\xF0", |chunks| {
+        let mut string = String::new();
+        {
+            let mut decoder = LossyDecoder::with_kind_replacement(|s| string.push_str(s), |kind| {
+                match kind {
+                    Utf8ErrorKind::Surrogate => "[S]",
+                    Utf8ErrorKind::Overlong => "[O]",
+                    Utf8ErrorKind::Truncated => "[T]",
+                    _ => REPLACEMENT_CHARACTER,
+                }
+            });
+            decoder.feed_all(chunks);
+        }
+        assert_eq!(string, "a[S]\u{FFFD}\u{FFFD}b[O]\u{FFFD}c[O]\u{FFFD}d[T]");
+    });
+}
+
+#[test]
+fn test_split_valid() {
+    for &(input, _) in DECODED_LOSSY {
+        let items: Vec<_> = split_valid(input).collect();
+        let mut concatenated = Vec::new();
+        for (i, &(valid, invalid)) in items.iter().enumerate() {
+            assert!(i == 0 || !valid.is_empty());
+            assert!(i == items.len() - 1 || !invalid.is_empty());
+            assert_eq!(valid_prefix_len(invalid), 0);
+            concatenated.extend_from_slice(valid.as_bytes());
+            concatenated.extend_from_slice(invalid);
+        }
+        assert_eq!(concatenated, input);
+    }
+    let items: Vec<_> = split_valid(b"\xFFab\xC0\x80\xFEcd\xE2\x82").collect();
+    assert_eq!(items, [("", &b"\xFF"[..]), ("ab", b"\xC0\x80\xFE"), ("cd", b"\xE2\x82")]);
+    assert_eq!(split_valid(b"abc").collect::<Vec<_>>(), [("abc", &b""[..])]);
+    assert_eq!(split_valid(b"").count(), 0);
+}
+
+#[test]
+fn test_replacement_char() {
+    assert_eq!(REPLACEMENT_CHAR.to_string(), REPLACEMENT_CHARACTER);
+    let mut chars = Vec::new();
+    CharDecoder::new(|c| chars.push(c)).feed(b"a\xFF");
+    assert_eq!(chars, ['a', REPLACEMENT_CHAR]);
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_lossy_decoder_feed_buf() {
+    use bytes::Buf;
+
+    for &(input, expected) in DECODED_LOSSY {
+        for split in 0..=input.len() {
+            let (a, b) = input.split_at(split);
+            let mut buf = a.chain(b);
+            let mut string = String::new();
+            LossyDecoder::new(|s| string.push_str(s)).feed_buf(&mut buf);
+            assert_eq!(string, expected);
+            assert!(!buf.has_remaining());
+        }
+    }
+}
+
+#[test]
+fn test_lossy_decoder_with_limit() {
+    for &(input, expected) in DECODED_LOSSY {
+        for limit in 0..expected.len() + 2 {
+            for &chunk_len in &[1, input.len() + 1] {
+                let mut string = String::new();
+                let truncated;
+                {
+                    let mut decoder = LossyDecoder::with_limit(|s| string.push_str(s), limit);
+                    decoder.feed_all(input.chunks(chunk_len));
+                    truncated = decoder.truncated();
+                }
+                let mut expected_len = std::cmp::min(limit, expected.len());
+                while !expected.is_char_boundary(expected_len) {
+                    expected_len -= 1
+                }
+                assert_eq!(string, &expected[..expected_len]);
+                // A trailing incomplete sequence is only replaced on drop, after this.
+                assert!(!truncated || limit < expected.len());
+            }
+        }
+    }
+
+    let mut string = String::new();
+    let mut decoder = LossyDecoder::with_limit(|s| string.push_str(s), 4);
+    decoder.feed(b"ab");
+    assert!(!decoder.truncated());
+    decoder.feed("cé".as_bytes());
+    assert!(decoder.truncated());
+    decoder.feed(b"d");
+    drop(decoder);
+    assert_eq!(string, "abc");
+}
+
+#[test]
+fn test_decode_lossy_to_slice() {
+    for &(input, expected) in DECODED_LOSSY {
+        for out_len in 4..12 {
+            let mut out = vec![0; out_len];
+            let mut output = Vec::new();
+            let mut remaining = input;
+            while !remaining.is_empty() {
+                let (consumed, written) = decode_lossy_to_slice(remaining, &mut out);
+                assert!(consumed > 0 && written > 0);
+                assert!(std::str::from_utf8(&out[..written]).is_ok());
+                output.extend_from_slice(&out[..written]);
+                remaining = &remaining[consumed..];
+            }
+            assert_eq!(std::str::from_utf8(&output).unwrap(), expected);
+        }
+    }
+    let mut out = [0; 2];
+    assert_eq!(decode_lossy_to_slice(b"a\xFF", &mut out), (1, 1));
+    assert_eq!(decode_lossy_to_slice(b"\xFF", &mut out), (0, 0));
+}
+
+#[test]
+fn test_sniff_bom() {
+    assert_eq!(sniff_bom(b"\xEF\xBB\xBFabc"), Some(Bom::Utf8));
+    assert_eq!(sniff_bom(b"\xFF\xFEa\x00"), Some(Bom::Utf16Le));
+    assert_eq!(sniff_bom(b"\xFE\xFF\x00a"), Some(Bom::Utf16Be));
+    assert_eq!(sniff_bom(b"\xFF\xFE\x00\x00a\x00\x00\x00"), Some(Bom::Utf32Le));
+    assert_eq!(sniff_bom(b"\x00\x00\xFE\xFF"), Some(Bom::Utf32Be));
+    assert_eq!(sniff_bom(b"\xEF\xBB"), None);
+    assert_eq!(sniff_bom(b"abc"), None);
+    assert_eq!(sniff_bom(b""), None);
+    assert_eq!(Bom::Utf32Le.as_bytes(), b"\xFF\xFE\x00\x00");
+}
+
+#[test]
+fn test_lossy_decoder_feed_returns_whether_pushed() {
+    let mut string = String::new();
+    {
+        let mut decoder = LossyDecoder::new(|s| string.push_str(s));
+        assert!(decoder.feed(b"a\xF0"));
+        assert!(!decoder.feed(b"\x9F"));
+        assert!(!decoder.feed(b"\x92"));
+        assert!(decoder.feed(b"\xA9"));
+        assert!(!decoder.feed(b""));
+        assert!(decoder.feed(b"\xFF"));
+    }
+    assert_eq!(string, "a\u{1F4A9}\u{FFFD}");
+}
+
+/// Lossy decoding with `decode` and `Incomplete::try_complete`,
+/// the way a user of the low-level API would drive them.
+fn decode_lossy_with_try_complete(chunks: &[&[u8]]) -> String {
+    let mut string = String::new();
+    let mut incomplete: Option<Incomplete> = None;
+    for &chunk in chunks {
+        let mut chunk = chunk;
+        if let Some(mut buffered) = incomplete.take() {
+            match buffered.try_complete(chunk) {
+                None => {
+                    incomplete = Some(buffered);
+                    continue
+                }
+                Some((result, remaining_input)) => {
+                    string.push_str(result.unwrap_or("\u{FFFD}"));
+                    chunk = remaining_input;
+                }
+            }
+        }
+        loop {
+            match decode(chunk) {
+                Ok(s) => {
+                    string.push_str(s);
+                    break
+                }
+                Err(DecodeError::Incomplete { valid_prefix, incomplete_suffix }) => {
+                    string.push_str(valid_prefix);
+                    incomplete = Some(incomplete_suffix);
+                    break
+                }
+                Err(DecodeError::Invalid { valid_prefix, remaining_input, .. }) => {
+                    string.push_str(valid_prefix);
+                    string.push('\u{FFFD}');
+                    chunk = remaining_input;
+                }
+            }
+        }
+    }
+    if incomplete.is_some() {
+        string.push('\u{FFFD}');
+    }
+    string
+}
+
+/// Bytes biased towards the boundaries of UTF-8 sequences, with runs of valid text,
+/// so that random inputs mix valid code points, ill-formed sequences and truncation.
+fn arbitrary_utf8ish() -> impl Strategy<Value = Vec<u8>> {
+    let piece = prop_oneof![
+        any::<u8>().prop_map(|byte| vec![byte]),
+        prop::sample::select(&[0x80_u8, 0xBF, 0xC0, 0xC2, 0xE0, 0xED, 0xF0, 0xF4, 0xF5][..])
+            .prop_map(|byte| vec![byte]),
+        any::<char>().prop_map(|c| c.to_string().into_bytes()),
+    ];
+    prop::collection::vec(piece, 0..32).prop_map(|pieces| pieces.concat())
+}
+
+/// Split `input` at the given points, in any order and possibly repeated.
+fn split_at_indices<'a>(input: &'a [u8], indices: &[Index]) -> Vec<&'a [u8]> {
+    let mut points: Vec<usize> = indices.iter().map(|i| i.index(input.len() + 1)).collect();
+    points.sort_unstable();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    for point in points.into_iter().chain(Some(input.len())) {
+        chunks.push(&input[start..point]);
+        start = point;
+    }
+    chunks
+}
+
+proptest! {
+    #[test]
+    fn prop_lossy_decoder_matches_decode_lossy(input in arbitrary_utf8ish(),
+                                               indices in prop::collection::vec(any::<Index>(), 0..8)) {
+        let expected = String::from_utf8_lossy(&input);
+        prop_assert_eq!(&decode_lossy(&input), &expected);
+
+        let mut string = String::new();
+        {
+            let mut decoder = LossyDecoder::new(|s: &str| {
+                assert!(!s.is_empty());
+                string.push_str(s)
+            });
+            for chunk in split_at_indices(&input, &indices) {
+                decoder.feed(chunk);
+            }
+        }
+        prop_assert_eq!(&string, &expected);
+
+        let mut decoder = OwningDecoder::new();
+        for chunk in split_at_indices(&input, &indices) {
+            decoder.feed(chunk);
+        }
+        prop_assert_eq!(&decoder.finish(), &expected);
+    }
+
+    #[test]
+    fn prop_incomplete_try_complete(input in arbitrary_utf8ish(),
+                                    indices in prop::collection::vec(any::<Index>(), 0..8)) {
+        let chunks = split_at_indices(&input, &indices);
+        prop_assert_eq!(decode_lossy_with_try_complete(&chunks), String::from_utf8_lossy(&input));
+    }
+}